@@ -1,134 +1,240 @@
+use std::collections::HashMap;
+
 use aoc_runner_derive::{aoc, aoc_generator};
-use nalgebra::SMatrix;
+use nalgebra::DMatrix;
 
-fn surrounding((i, j): (usize, usize), (rows, cols): (usize, usize)) -> Vec<(usize, usize)> {
-    let mut vec = Vec::new();
-    if i > 0 {
-        if j > 0 {
-            vec.push((i - 1, j - 1));
-        }
-        vec.push((i - 1, j));
-        if j < cols - 1 {
-            vec.push((i - 1, j + 1));
+/// which cells receive energy from a flashing octopus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// the 4 orthogonal neighbours
+    Orthogonal,
+    /// the 8 orthogonal and diagonal neighbours
+    Full,
+    /// the 6 neighbours of a hex grid stored as rows, odd rows shifted half a cell right
+    Hex,
+}
+
+/// what lies beyond the edges of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    /// nothing, energy is lost at the edge
+    Bounded,
+    /// the grid wraps around on both axes
+    Toroidal,
+}
+
+/// the energy rules driving the octopus simulation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctopusRules {
+    /// an octopus flashes once its energy exceeds this level
+    pub threshold: u8,
+    /// energy level of an octopus after it flashed
+    pub reset: u8,
+    pub neighbourhood: Neighbourhood,
+    pub boundary: Boundary,
+    /// energy gained by each octopus at the start of a step, 1 everywhere if unset
+    pub increments: Option<DMatrix<u8>>,
+}
+
+impl Default for OctopusRules {
+    fn default() -> Self {
+        OctopusRules {
+            threshold: 9,
+            reset: 0,
+            neighbourhood: Neighbourhood::Full,
+            boundary: Boundary::Bounded,
+            increments: None,
         }
     }
+}
 
-    if j > 0 {
-        vec.push((i, j - 1));
+impl OctopusRules {
+    fn offsets(&self, row: usize) -> &'static [(isize, isize)] {
+        match (self.neighbourhood, row % 2) {
+            (Neighbourhood::Orthogonal, _) => &[(-1, 0), (0, -1), (0, 1), (1, 0)],
+            (Neighbourhood::Full, _) => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+            (Neighbourhood::Hex, 0) => &[(-1, -1), (-1, 0), (0, -1), (0, 1), (1, -1), (1, 0)],
+            (Neighbourhood::Hex, _) => &[(-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0), (1, 1)],
+        }
     }
 
-    if j < cols - 1 {
-        vec.push((i, j + 1));
-    }
+    fn neighbours(
+        &self,
+        (i, j): (usize, usize),
+        (rows, cols): (usize, usize),
+    ) -> Vec<(usize, usize)> {
+        let mut vec = self
+            .offsets(i)
+            .iter()
+            .filter_map(|&(di, dj)| {
+                let (ni, nj) = (i as isize + di, j as isize + dj);
+                match self.boundary {
+                    Boundary::Bounded => {
+                        let inside =
+                            (0..rows as isize).contains(&ni) && (0..cols as isize).contains(&nj);
+                        inside.then_some((ni as usize, nj as usize))
+                    }
+                    Boundary::Toroidal => Some((
+                        ni.rem_euclid(rows as isize) as usize,
+                        nj.rem_euclid(cols as isize) as usize,
+                    )),
+                }
+            })
+            .filter(|&coord| coord != (i, j))
+            .collect::<Vec<_>>();
 
-    if i < rows - 1 {
-        if j > 0 {
-            vec.push((i + 1, j - 1));
-        }
-        vec.push((i + 1, j));
-        if j < cols - 1 {
-            vec.push((i + 1, j + 1));
-        }
+        // tiny toroidal grids can reach the same cell from both sides
+        vec.sort_unstable();
+        vec.dedup();
+        vec
     }
 
-    vec
+    fn increment(&self, coord: (usize, usize)) -> u8 {
+        self.increments
+            .as_ref()
+            .map_or(1, |increments| increments[coord])
+    }
 }
 
-fn step(input: &SMatrix<u8, 10, 10>) -> (SMatrix<u8, 10, 10>, usize) {
-    let (rows, cols) = input.shape();
-    let mut flash_mat = input.add_scalar(1);
-
-    let mut last_flashes = 1;
-    let mut flashes = 0;
-    let mut flashers = Vec::with_capacity(100);
+fn step(input: &DMatrix<u8>, rules: &OctopusRules) -> (DMatrix<u8>, usize) {
+    let shape = input.shape();
+    let mut flash_mat = input.clone();
+    let coords = (0..shape.0)
+        .flat_map(|i| (0..shape.1).map(move |j| (i, j)))
+        .collect::<Vec<_>>();
 
-    while flashes != last_flashes || (last_flashes == 0 && flashes != 0) {
-        last_flashes = flashes;
-        for i in 0..rows {
-            for j in 0..cols {
-                let val = flash_mat[(i, j)];
-                if val > 9 && !flashers.contains(&(i, j)) {
-                    // add 1 to surrounding
-                    for coord in surrounding((i, j), (rows, cols)) {
-                        flash_mat[coord] += 1;
-                    }
-                    flashes += 1;
-                    flashers.push((i, j));
-                }
-            }
-        }
+    for &coord in &coords {
+        flash_mat[coord] = flash_mat[coord].saturating_add(rules.increment(coord));
     }
 
-    for i in 0..rows {
-        for j in 0..cols {
-            let val = flash_mat[(i, j)];
-            if val > 9 {
-                // add 1 to surrounding
-                flash_mat[(i, j)] = 0;
+    // a cell joins the worklist exactly once, when its energy first exceeds the threshold
+    let mut worklist = coords
+        .into_iter()
+        .filter(|&coord| flash_mat[coord] > rules.threshold)
+        .collect::<Vec<_>>();
+    let mut flashed = worklist.clone();
+
+    while let Some(coord) = worklist.pop() {
+        for neighbour in rules.neighbours(coord, shape) {
+            let before = flash_mat[neighbour];
+            flash_mat[neighbour] = before.saturating_add(1);
+            if before <= rules.threshold && flash_mat[neighbour] > rules.threshold {
+                worklist.push(neighbour);
+                flashed.push(neighbour);
             }
         }
     }
 
-    (flash_mat, last_flashes)
+    flashed
+        .iter()
+        .for_each(|&coord| flash_mat[coord] = rules.reset);
+
+    (flash_mat, flashed.len())
 }
 
-fn steps(input: &SMatrix<u8, 10, 10>, steps: usize) -> (SMatrix<u8, 10, 10>, usize) {
-    (0..steps).fold((*input, 0), |(mat, flashcnt), _| {
-        let (next_mat, flashes) = step(&mat);
+fn steps(input: &DMatrix<u8>, steps: usize) -> (DMatrix<u8>, usize) {
+    let rules = OctopusRules::default();
+    (0..steps).fold((input.clone(), 0), |(mat, flashcnt), _| {
+        let (next_mat, flashes) = step(&mat, &rules);
         (next_mat, flashcnt + flashes)
     })
 }
 
-fn flash_count(input: &SMatrix<u8, 10, 10>, stepcnt: usize) -> usize {
+fn flash_count(input: &DMatrix<u8>, stepcnt: usize) -> usize {
     steps(input, stepcnt).1
 }
 
-fn parse_input(input: &str) -> SMatrix<u8, 10, 10> {
-    let mut mat = SMatrix::<u8, 10, 10>::zeros();
-
-    input.lines().enumerate().for_each(|(i, row)| {
-        row.trim()
-            .as_bytes()
-            .iter()
-            .enumerate()
-            .for_each(|(j, chr)| {
-                mat[(i, j)] = *chr - 48;
-            });
-    });
+/// number of flashes in each of the first `steps` steps
+pub fn flash_series(input: &DMatrix<u8>, rules: &OctopusRules, steps: usize) -> Vec<usize> {
+    let mut mat = input.clone();
+    (0..steps)
+        .map(|_| {
+            let (next_mat, flashes) = step(&mat, rules);
+            mat = next_mat;
+            flashes
+        })
+        .collect()
+}
 
-    mat
+/// the first step in which every octopus flashes, searching up to `limit` steps
+pub fn first_synchronised(
+    input: &DMatrix<u8>,
+    rules: &OctopusRules,
+    limit: usize,
+) -> Option<usize> {
+    let mut mat = input.clone();
+    (1..=limit).find(|_| {
+        let (next_mat, flashes) = step(&mat, rules);
+        mat = next_mat;
+        flashes == mat.len()
+    })
 }
 
-fn solve_p1(input: &SMatrix<u8, 10, 10>) -> usize {
-    flash_count(input, 100)
+/// a cycle in the grid state, the state after `start` steps recurs every `length` steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub start: usize,
+    pub length: usize,
 }
 
-fn solve_p2(input: &SMatrix<u8, 10, 10>) -> usize {
-    let mut current_mat = *input;
+/// finds the first repeated grid state within `limit` steps
+pub fn find_period(input: &DMatrix<u8>, rules: &OctopusRules, limit: usize) -> Option<Period> {
+    let mut seen = HashMap::new();
+    let mut mat = input.clone();
 
-    for iter in 0.. {
-        let (next_mat, _) = step(&current_mat);
-        if next_mat.iter().fold(0, |agg, i| agg + *i as usize) == 0 {
-            return iter + 1;
+    for iter in 0..=limit {
+        if let Some(start) = seen.insert(mat.clone(), iter) {
+            return Some(Period {
+                start,
+                length: iter - start,
+            });
         }
-        current_mat = next_mat;
+        mat = step(&mat, rules).0;
     }
 
-    0
+    None
+}
+
+fn parse_input(input: &str) -> DMatrix<u8> {
+    let rows = input
+        .lines()
+        .map(|row| row.trim().bytes().map(|chr| chr - b'0').collect::<Vec<_>>())
+        .filter(|row| !row.is_empty())
+        .collect::<Vec<_>>();
+    let cols = rows.first().map_or(0, Vec::len);
+
+    DMatrix::from_row_slice(rows.len(), cols, &rows.concat())
+}
+
+fn solve_p1(input: &DMatrix<u8>) -> usize {
+    flash_count(input, 100)
+}
+
+fn solve_p2(input: &DMatrix<u8>) -> usize {
+    first_synchronised(input, &OctopusRules::default(), usize::MAX).unwrap_or(0)
 }
 
 #[aoc_generator(day11)]
-pub fn input_generator(input: &str) -> SMatrix<u8, 10, 10> {
+pub fn input_generator(input: &str) -> DMatrix<u8> {
     parse_input(input)
 }
 
 #[aoc(day11, part1)]
-pub fn wrapper_p1(input: &SMatrix<u8, 10, 10>) -> usize {
+pub fn wrapper_p1(input: &DMatrix<u8>) -> usize {
     solve_p1(input)
 }
 
 #[aoc(day11, part2)]
-pub fn wrapper_p2(input: &SMatrix<u8, 10, 10>) -> usize {
+pub fn wrapper_p2(input: &DMatrix<u8>) -> usize {
     solve_p2(input)
 }
 
@@ -170,4 +276,66 @@ mod tests {
 
         assert_eq!(195, super::solve_p2(&parsed_input));
     }
+
+    #[test]
+    fn analysis() {
+        let input = "5483143223\n2745854711\n5264556173\n6141336146\n6357385478\n4167524645\n2176841721\n6882881134\n4846848554\n5283751526";
+        let parsed_input = super::parse_input(input);
+
+        let rules = super::OctopusRules::default();
+        let series = super::flash_series(&parsed_input, &rules, 195);
+        assert_eq!(204, series[..10].iter().sum::<usize>());
+        assert_eq!(Some(&100), series.last());
+        assert_eq!(
+            Some(195),
+            super::first_synchronised(&parsed_input, &rules, 500)
+        );
+        assert_eq!(None, super::first_synchronised(&parsed_input, &rules, 194));
+
+        let period = super::find_period(&parsed_input, &rules, 500).unwrap();
+        assert_eq!(10, period.length);
+        assert!(period.start <= 195);
+
+        // a non-square grid
+        let parsed_input = super::parse_input("11111\n19991\n19191\n19991\n11111\n99999\n");
+        assert_eq!((6, 5), parsed_input.shape());
+        assert_eq!(vec![14, 0], super::flash_series(&parsed_input, &rules, 2));
+    }
+
+    #[test]
+    fn custom_rules() {
+        use super::{Boundary, Neighbourhood, OctopusRules};
+
+        let parsed_input = super::parse_input("000\n050\n000");
+        let rules = OctopusRules {
+            threshold: 5,
+            reset: 2,
+            neighbourhood: Neighbourhood::Orthogonal,
+            ..Default::default()
+        };
+        let (mat, flashes) = super::step(&parsed_input, &rules);
+        assert_eq!(1, flashes);
+        assert_eq!(super::parse_input("121\n222\n121"), mat);
+
+        // wrapping around lets the corner reach the far corners too
+        let parsed_input = super::parse_input("0000\n0000\n0000\n0009");
+        let rules = OctopusRules {
+            boundary: Boundary::Toroidal,
+            ..Default::default()
+        };
+        let (mat, flashes) = super::step(&parsed_input, &rules);
+        assert_eq!(1, flashes);
+        assert_eq!(super::parse_input("2122\n1111\n2122\n2120"), mat);
+
+        // odd rows are shifted right on the hex grid
+        let rules = OctopusRules {
+            neighbourhood: Neighbourhood::Hex,
+            increments: Some(super::parse_input("000\n090\n000")),
+            threshold: 8,
+            ..Default::default()
+        };
+        let (mat, flashes) = super::step(&super::parse_input("000\n000\n000"), &rules);
+        assert_eq!(1, flashes);
+        assert_eq!(super::parse_input("011\n101\n011"), mat);
+    }
 }
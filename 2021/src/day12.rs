@@ -1,6 +1,10 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use itertools::Itertools;
 use nalgebra::DMatrix;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -25,158 +29,242 @@ impl Cave {
             }
         }
     }
+}
 
-    fn compatible(&self, path: &[Cave]) -> bool {
-        match self {
-            Cave::Start => false,
-            Cave::End | Cave::Large(_) => true,
-            Cave::Small(_) => !path.iter().any(|cave| cave == self),
-        }
+/// cave graph keeping the original cave names alongside the adjacency matrix
+#[derive(Debug, Clone)]
+pub struct CaveSystem {
+    names: Vec<String>,
+    nodes: Vec<Cave>,
+    tunnels: DMatrix<u8>,
+}
+
+impl CaveSystem {
+    pub fn names(&self) -> &[String] {
+        &self.names
     }
 
-    fn is_small(&self) -> bool {
-        matches!(self, Cave::Small(_))
+    pub fn nodes(&self) -> &[Cave] {
+        &self.nodes
     }
 
-    fn compatible2(&self, path: &[Cave]) -> bool {
-        match self {
-            Cave::Start => false,
-            Cave::End | Cave::Large(_) => true,
-            Cave::Small(_) => {
-                if !path.contains(self) {
-                    return true;
-                }
-                let small_node_counts = path.iter().filter(|node| node.is_small()).fold(
-                    HashMap::<Cave, u8>::new(),
-                    |mut agg, node| {
-                        agg.entry(*node).and_modify(|i| *i += 1).or_insert(1);
-                        agg
-                    },
-                );
-
-                !small_node_counts.iter().any(|(_, cnt)| *cnt == 2)
-            }
-        }
+    fn start(&self) -> Option<usize> {
+        self.nodes.iter().position(|cave| *cave == Cave::Start)
     }
-}
 
-fn parse_input(input: &str) -> (Vec<Cave>, DMatrix<u8>) {
-    let mut nodes = Vec::new();
-    let mut node_names = Vec::new();
-    let mut tunnels = DMatrix::zeros(0, 0);
+    fn neighbours(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(move |&next| self.tunnels[(node, next)] != 0)
+    }
 
-    input.lines().for_each(|line| {
-        let pair = line.split('-').map(str::to_owned).collect::<Vec<_>>();
-        if !node_names.contains(&pair[0]) {
-            let (rows, cols) = tunnels.shape();
+    /// the (visited, revisits) state after stepping into `next`, if allowed
+    fn enter(&self, next: usize, visited: u64, revisits: usize) -> Option<(u64, usize)> {
+        match self.nodes[next] {
+            Cave::Start => None,
+            Cave::End | Cave::Large(_) => Some((visited, revisits)),
+            Cave::Small(_) if visited & (1 << next) == 0 => Some((visited | (1 << next), revisits)),
+            Cave::Small(_) if revisits > 0 => Some((visited, revisits - 1)),
+            Cave::Small(_) => None,
+        }
+    }
 
-            node_names.push(pair[0].clone());
-            let node_id = node_names.len() - 1;
-            nodes.push(Cave::new(node_id, &node_names[node_id]));
+    /// lazily yields every valid path as `start,A,b,end`, with the same revisit budget as
+    /// count_paths
+    pub fn paths(&self, revisits: usize) -> Paths<'_> {
+        assert!(self.nodes.len() <= 64, "at most 64 caves are supported");
+
+        let stack = self
+            .start()
+            .map(|start| Frame {
+                node: start,
+                next: 0,
+                visited: 1 << start,
+                revisits,
+            })
+            .into_iter()
+            .collect();
+
+        Paths {
+            system: self,
+            stack,
+        }
+    }
 
-            // add row, col to DMatrix
-            tunnels = tunnels.clone().insert_row(rows, 0);
-            tunnels = tunnels.clone().insert_column(cols, 0);
+    /// writes the graph in graphviz dot format. small caves are circles, large caves boxes and
+    /// start/end double circles.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "graph caves {{")?;
+        for (name, cave) in self.names.iter().zip(&self.nodes) {
+            let shape = match cave {
+                Cave::Start | Cave::End => "doublecircle",
+                Cave::Small(_) => "circle",
+                Cave::Large(_) => "box",
+            };
+            writeln!(out, "    \"{}\" [shape={}];", name, shape)?;
+        }
+        for (first, second) in (0..self.nodes.len()).tuple_combinations() {
+            if self.tunnels[(first, second)] != 0 {
+                writeln!(
+                    out,
+                    "    \"{}\" -- \"{}\";",
+                    self.names[first], self.names[second]
+                )?;
+            }
         }
+        writeln!(out, "}}")
+    }
+}
 
-        if !node_names.contains(&pair[1]) {
-            let (rows, cols) = tunnels.shape();
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    node: usize,
+    next: usize,
+    visited: u64,
+    revisits: usize,
+}
 
-            node_names.push(pair[1].clone());
-            let node_id = node_names.len() - 1;
-            nodes.push(Cave::new(node_id, &node_names[node_id]));
+/// depth-first path iterator returned by CaveSystem::paths
+pub struct Paths<'a> {
+    system: &'a CaveSystem,
+    stack: Vec<Frame>,
+}
 
-            // add row, col to DMatrix
-            tunnels = tunnels.clone().insert_row(rows, 0);
-            tunnels = tunnels.clone().insert_column(cols, 0);
-        }
+impl Iterator for Paths<'_> {
+    type Item = String;
 
-        let first_id = node_names.iter().position(|cave| *cave == pair[0]).unwrap();
-        let second_id = node_names.iter().position(|cave| *cave == pair[1]).unwrap();
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(frame) = self.stack.last_mut() {
+            if self.system.nodes[frame.node] == Cave::End {
+                let path = self
+                    .stack
+                    .iter()
+                    .map(|frame| self.system.names[frame.node].as_str())
+                    .join(",");
+                self.stack.pop();
+                return Some(path);
+            }
 
-        tunnels[(first_id, second_id)] = 1;
-        tunnels[(second_id, first_id)] = 1;
-    });
+            match self
+                .system
+                .neighbours(frame.node)
+                .find(|&next| next >= frame.next)
+            {
+                Some(next) => {
+                    frame.next = next + 1;
+                    if let Some((visited, revisits)) =
+                        self.system.enter(next, frame.visited, frame.revisits)
+                    {
+                        self.stack.push(Frame {
+                            node: next,
+                            next: 0,
+                            visited,
+                            revisits,
+                        });
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
 
-    (nodes, tunnels)
+        None
+    }
 }
 
-fn solve_p1((nodes, tunnels): &(Vec<Cave>, DMatrix<u8>)) -> usize {
-    let mut paths = VecDeque::new();
-    paths.push_back(vec![Cave::Start]);
+fn parse_input(input: &str) -> CaveSystem {
+    let mut names = Vec::<String>::new();
+    let edges = input
+        .lines()
+        .map(|line| {
+            let mut ids = line.split('-').map(|name| {
+                names
+                    .iter()
+                    .position(|known| known == name)
+                    .unwrap_or_else(|| {
+                        names.push(name.to_owned());
+                        names.len() - 1
+                    })
+            });
+            (ids.next().unwrap(), ids.next().unwrap())
+        })
+        .collect::<Vec<_>>();
+
+    let nodes = names
+        .iter()
+        .enumerate()
+        .map(|(id, name)| Cave::new(id, name))
+        .collect();
+
+    let mut tunnels = DMatrix::zeros(names.len(), names.len());
+    for (first, second) in edges {
+        tunnels[(first, second)] = 1;
+        tunnels[(second, first)] = 1;
+    }
 
-    while !paths.iter().all(|path| path.last() == Some(&Cave::End)) {
-        let pathcnt = paths.len();
-        for _ in 0..pathcnt {
-            let path = paths.pop_front().unwrap();
-            let node = path.last().unwrap();
+    CaveSystem {
+        names,
+        nodes,
+        tunnels,
+    }
+}
 
-            if node == &Cave::End {
-                paths.push_back(path);
-            } else {
-                let node_id = nodes.iter().position(|cave| cave == node).unwrap();
+/// counts paths visiting small caves once, plus up to `revisits` repeat visits, memoized on
+/// (cave, visited small caves, revisits left). assumes no two large caves are connected.
+pub fn count_paths(system: &CaveSystem, revisits: usize) -> usize {
+    assert!(system.nodes.len() <= 64, "at most 64 caves are supported");
 
-                tunnels
-                    .row(node_id)
-                    .iter()
-                    .enumerate()
-                    .filter(|(id, &tunnel)| tunnel == 1 && nodes[*id].compatible(&path))
-                    .for_each(|(id, _)| {
-                        let mut new_path = path.clone();
-                        new_path.push(nodes[id]);
-                        paths.push_back(new_path);
-                    });
-            }
-        }
+    match system.start() {
+        Some(start) => count_from(system, start, 1 << start, revisits, &mut HashMap::new()),
+        None => 0,
     }
-
-    paths.len()
 }
 
-fn solve_p2((nodes, tunnels): &(Vec<Cave>, DMatrix<u8>)) -> usize {
-    let mut paths = VecDeque::new();
-    paths.push_back(vec![Cave::Start]);
+fn count_from(
+    system: &CaveSystem,
+    node: usize,
+    visited: u64,
+    revisits: usize,
+    memo: &mut HashMap<(usize, u64, usize), usize>,
+) -> usize {
+    if system.nodes[node] == Cave::End {
+        return 1;
+    }
+    if let Some(&count) = memo.get(&(node, visited, revisits)) {
+        return count;
+    }
 
-    while !paths.iter().all(|path| path.last() == Some(&Cave::End)) {
-        let pathcnt = paths.len();
-        for _ in 0..pathcnt {
-            let path = paths.pop_front().unwrap();
-            let node = path.last().unwrap();
+    let count = system
+        .neighbours(node)
+        .filter_map(|next| {
+            let (visited, revisits) = system.enter(next, visited, revisits)?;
+            Some(count_from(system, next, visited, revisits, memo))
+        })
+        .sum();
 
-            if node == &Cave::End {
-                paths.push_back(path);
-            } else {
-                let node_id = nodes.iter().position(|cave| cave == node).unwrap();
+    memo.insert((node, visited, revisits), count);
+    count
+}
 
-                tunnels
-                    .row(node_id)
-                    .iter()
-                    .enumerate()
-                    .filter(|(id, &tunnel)| tunnel == 1 && nodes[*id].compatible2(&path))
-                    .for_each(|(id, _)| {
-                        let mut new_path = path.clone();
-                        new_path.push(nodes[id]);
-                        paths.push_back(new_path);
-                    });
-            }
-        }
-    }
+fn solve_p1(input: &CaveSystem) -> usize {
+    count_paths(input, 0)
+}
 
-    paths.len()
+fn solve_p2(input: &CaveSystem) -> usize {
+    count_paths(input, 1)
 }
 
 #[aoc_generator(day12)]
-pub fn input_generator(input: &str) -> (Vec<Cave>, DMatrix<u8>) {
+pub fn input_generator(input: &str) -> CaveSystem {
     parse_input(input)
 }
 
 #[aoc(day12, part1)]
-pub fn wrapper_p1(input: &(Vec<Cave>, DMatrix<u8>)) -> usize {
+pub fn wrapper_p1(input: &CaveSystem) -> usize {
     solve_p1(input)
 }
 
 #[aoc(day12, part2)]
-pub fn wrapper_p2(input: &(Vec<Cave>, DMatrix<u8>)) -> usize {
+pub fn wrapper_p2(input: &CaveSystem) -> usize {
     solve_p2(input)
 }
 
@@ -203,4 +291,48 @@ mod tests {
         assert_eq!(226, super::solve_p1(&parsed_input));
         assert_eq!(3509, super::solve_p2(&parsed_input));
     }
+
+    #[test]
+    fn revisit_budget() {
+        let input = "start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end";
+
+        let parsed_input = super::parse_input(input);
+        assert_eq!(10, super::count_paths(&parsed_input, 0));
+        assert_eq!(36, super::count_paths(&parsed_input, 1));
+
+        let counts = (0..4)
+            .map(|revisits| super::count_paths(&parsed_input, revisits))
+            .collect::<Vec<_>>();
+        assert!(counts.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let parsed_input = super::parse_input("start-a\na-end");
+        assert_eq!(1, super::count_paths(&parsed_input, 5));
+    }
+
+    #[test]
+    fn paths_and_dot() {
+        let input = "start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end";
+
+        let system = super::parse_input(input);
+        assert_eq!(system.names()[..3], ["start", "A", "b"]);
+
+        let paths = system.paths(0).collect::<Vec<_>>();
+        assert_eq!(10, paths.len());
+        assert!(paths.contains(&"start,A,b,A,c,A,end".to_owned()));
+        assert!(paths.contains(&"start,b,end".to_owned()));
+        assert_eq!(36, system.paths(1).count());
+        assert_eq!(
+            Some("start,A,b,A,b,A,end".to_owned()),
+            system.paths(1).find(|path| path == "start,A,b,A,b,A,end")
+        );
+
+        let mut dot = Vec::new();
+        system.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("graph caves {\n"));
+        assert!(dot.contains("\"A\" [shape=box];"));
+        assert!(dot.contains("\"c\" [shape=circle];"));
+        assert!(dot.contains("\"start\" [shape=doublecircle];"));
+        assert_eq!(7, dot.matches(" -- ").count());
+    }
 }
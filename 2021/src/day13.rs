@@ -1,9 +1,50 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    fmt::{self, Display},
+};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 
-type Paper = (Vec<(usize, usize)>, Vec<(usize, usize)>);
+/// a fold along the vertical line `x = n` or the horizontal line `y = n`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fold {
+    X(usize),
+    Y(usize),
+}
+
+/// sparse set of dot positions as (x, y), kept ordered and free of duplicates
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dots(BTreeSet<(usize, usize)>);
+
+impl Dots {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, dot: (usize, usize)) -> bool {
+        self.0.contains(&dot)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromIterator<(usize, usize)> for Dots {
+    fn from_iter<I: IntoIterator<Item = (usize, usize)>>(iter: I) -> Self {
+        Dots(iter.into_iter().collect())
+    }
+}
+
+type Paper = (Dots, Vec<Fold>);
 
 fn parse_input(input: &str) -> Paper {
-    let mut dots = Vec::new();
+    let mut dots = Dots::default();
     let mut actions = Vec::new();
 
     let mut nodes_done = false;
@@ -15,10 +56,10 @@ fn parse_input(input: &str) -> Paper {
                 let len = first.len();
                 match first.as_bytes()[len - 1] {
                     b'x' => {
-                        actions.push((rest.parse().unwrap(), 0));
+                        actions.push(Fold::X(rest.parse().unwrap()));
                     }
                     b'y' => {
-                        actions.push((0, rest.parse().unwrap()));
+                        actions.push(Fold::Y(rest.parse().unwrap()));
                     }
                     _ => unreachable!(),
                 }
@@ -29,20 +70,21 @@ fn parse_input(input: &str) -> Paper {
                 .map(str::parse::<usize>)
                 .map(Result::unwrap)
                 .collect::<Vec<_>>();
-            dots.push((pair[0], pair[1]));
+            dots.0.insert((pair[0], pair[1]));
         }
     });
 
     (dots, actions)
 }
 
-fn print_dots(dots: &[(usize, usize)]) {
-    let max_x = dots.iter().max_by_key(|x| x.0).unwrap().0;
-    let max_y = dots.iter().max_by_key(|x| x.1).unwrap().1;
+#[allow(unused)]
+fn print_dots(dots: &Dots) {
+    let max_x = dots.iter().map(|(x, _)| x).max().unwrap();
+    let max_y = dots.iter().map(|(_, y)| y).max().unwrap();
 
     for y in 0..=max_y {
         for x in 0..=max_x {
-            if dots.contains(&(x, y)) {
+            if dots.contains((x, y)) {
                 print!("#");
             } else {
                 print!(" ");
@@ -52,47 +94,210 @@ fn print_dots(dots: &[(usize, usize)]) {
     }
 }
 
-fn fold_once(dots: &[(usize, usize)], fold: (usize, usize)) -> Vec<(usize, usize)> {
+/// the 4x6 capital letter font used by the puzzle, glyphs are laid out left to right with one
+/// blank column between them
+const FONT: [(char, [&str; 6]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...", "#...", ".#.#", "..#.", "..#.", "..#."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+const GLYPH_SPACING: usize = GLYPH_WIDTH + 1;
+
+fn glyph_mask(rows: &[&str; GLYPH_HEIGHT]) -> u32 {
+    rows.iter()
+        .flat_map(|row| row.bytes())
+        .enumerate()
+        .filter(|(_, pixel)| *pixel == b'#')
+        .fold(0, |mask, (bit, _)| mask | 1 << bit)
+}
+
+/// glyphs that could not be matched against the font, by position from the left. `text` holds
+/// the partial reading with `?` in their place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcrError {
+    pub positions: Vec<usize>,
+    pub text: String,
+}
+
+impl Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unrecognised glyphs at positions {:?} in {:?}",
+            self.positions, self.text
+        )
+    }
+}
+
+impl std::error::Error for OcrError {}
+
+/// reads the letters spelled by a dot set whose top left glyph starts at (0, 0)
+pub fn read_letters(dots: &Dots) -> Result<String, OcrError> {
+    let glyphs = dots
+        .iter()
+        .map(|(x, _)| x / GLYPH_SPACING + 1)
+        .max()
+        .unwrap_or(0);
+    let mut masks = vec![Some(0u32); glyphs];
+
+    dots.iter().for_each(|(x, y)| {
+        let (glyph, col) = (x / GLYPH_SPACING, x % GLYPH_SPACING);
+        masks[glyph] = masks[glyph]
+            .filter(|_| col < GLYPH_WIDTH && y < GLYPH_HEIGHT)
+            .map(|mask| mask | 1 << (y * GLYPH_WIDTH + col));
+    });
+
+    let letters = masks
+        .iter()
+        .map(|mask| {
+            mask.and_then(|mask| {
+                FONT.iter()
+                    .find(|(_, rows)| glyph_mask(rows) == mask)
+                    .map(|&(letter, _)| letter)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let text = letters.iter().map(|letter| letter.unwrap_or('?')).collect();
+    let positions = letters
+        .iter()
+        .enumerate()
+        .filter(|(_, letter)| letter.is_none())
+        .map(|(position, _)| position)
+        .collect::<Vec<_>>();
+
+    if positions.is_empty() {
+        Ok(text)
+    } else {
+        Err(OcrError { positions, text })
+    }
+}
+
+/// folds the far (right or bottom) half onto the near half. when the far half reaches further
+/// from the line, the result is as wide as the far half and near dots shift away from the origin
+/// to make room. dots on the fold line itself are dropped.
+fn fold_once(dots: &Dots, fold: Fold) -> Dots {
+    let (line, far) = match fold {
+        Fold::X(line) => (line, dots.iter().map(|(x, _)| x).max()),
+        Fold::Y(line) => (line, dots.iter().map(|(_, y)| y).max()),
+    };
+    let extent = line.max(far.unwrap_or(0).saturating_sub(line));
+
+    let reflect = |along: usize| match along.cmp(&line) {
+        Ordering::Less => Some(along + extent - line),
+        Ordering::Equal => None,
+        Ordering::Greater => Some(extent - (along - line)),
+    };
+
     dots.iter()
-        .map(|(x, y)| match fold {
-            (fx, 0) => {
-                if x > &fx {
-                    (fx * 2 - x, *y)
-                } else {
-                    (*x, *y)
-                }
-            }
-            (0, fy) => {
-                if y > &fy {
-                    (*x, fy * 2 - y)
-                } else {
-                    (*x, *y)
-                }
-            }
-            _ => unreachable!(),
+        .filter_map(|(x, y)| match fold {
+            Fold::X(_) => Some((reflect(x)?, y)),
+            Fold::Y(_) => Some((x, reflect(y)?)),
         })
         .collect()
 }
 
 fn solve_p1((dots, folds): &Paper) -> usize {
-    let mut dots = fold_once(dots, folds[0]);
+    fold_once(dots, folds[0]).len()
+}
+
+fn solve_p2((dots, folds): &Paper) -> Result<String, OcrError> {
+    let dots = folds
+        .iter()
+        .fold(dots.clone(), |dots, fold| fold_once(&dots, *fold));
 
-    dots.sort_unstable();
-    dots.dedup();
-    dots.len()
+    read_letters(&dots)
 }
 
-fn solve_p2((dots, folds): &Paper) -> usize {
-    let dots = folds.iter().fold(dots.clone(), |dots, fold| {
-        let mut dots = fold_once(&dots, *fold);
-        dots.sort_unstable();
-        dots.dedup();
-        dots
-    });
+/// small xorshift generator, so generated puzzles are reproducible per seed
+struct XorShift(u64);
 
-    print_dots(&dots);
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// builds a puzzle whose dots spell `text` after its `folds` folds. the paper is unfolded one axis
+/// at a time and each dot lands on the near side, the mirrored far side or both, so the decoys
+/// overlap exactly when folded. none if `text` has letters missing from the font.
+pub fn generate(text: &str, folds: usize, seed: u64) -> Option<Paper> {
+    let mut dots = Vec::new();
+    for (position, letter) in text.chars().enumerate() {
+        let (_, rows) = FONT.iter().find(|(glyph, _)| *glyph == letter)?;
+        for (y, row) in rows.iter().enumerate() {
+            for (x, _) in row.bytes().enumerate().filter(|(_, pixel)| *pixel == b'#') {
+                dots.push((position * GLYPH_SPACING + x, y));
+            }
+        }
+    }
+
+    let mut rng = XorShift(seed | 1);
+    let (mut width, mut height) = ((text.len() * GLYPH_SPACING).max(1), GLYPH_HEIGHT);
+    let mut unfolds = Vec::new();
+    for _ in 0..folds {
+        let along_x = rng.next() & 1 == 0;
+        let line = if along_x { width } else { height };
+
+        dots = dots
+            .iter()
+            .flat_map(|&(x, y)| {
+                let mirrored = if along_x {
+                    (2 * line - x, y)
+                } else {
+                    (x, 2 * line - y)
+                };
+                match rng.next() % 3 {
+                    0 => vec![(x, y)],
+                    1 => vec![mirrored],
+                    _ => vec![(x, y), mirrored],
+                }
+            })
+            .collect();
+
+        if along_x {
+            width = 2 * width + 1;
+            unfolds.push(Fold::X(line));
+        } else {
+            height = 2 * height + 1;
+            unfolds.push(Fold::Y(line));
+        }
+    }
 
-    dots.len()
+    unfolds.reverse();
+    Some((dots.into_iter().collect(), unfolds))
+}
+
+/// formats a puzzle in the input format read by parse_input
+pub fn write_input((dots, folds): &Paper) -> String {
+    let dots = dots.iter().map(|(x, y)| format!("{},{}\n", x, y));
+    let folds = folds.iter().map(|fold| match fold {
+        Fold::X(x) => format!("fold along x={}\n", x),
+        Fold::Y(y) => format!("fold along y={}\n", y),
+    });
+
+    dots.chain(["\n".to_owned()]).chain(folds).collect()
 }
 
 #[aoc_generator(day13)]
@@ -106,7 +311,7 @@ pub fn wrapper_p1(input: &Paper) -> usize {
 }
 
 #[aoc(day13, part2)]
-pub fn wrapper_p2(input: &Paper) -> usize {
+pub fn wrapper_p2(input: &Paper) -> Result<String, OcrError> {
     solve_p2(input)
 }
 
@@ -122,6 +327,95 @@ mod tests {
         super::print_dots(&parsed_input.0);
         println!("{:?}", &parsed_input.1);
         assert_eq!(17, super::solve_p1(&parsed_input));
-        assert_eq!(16, super::solve_p2(&parsed_input));
+        assert_eq!(
+            Err(super::OcrError {
+                positions: vec![0],
+                text: "?".to_owned(),
+            }),
+            super::solve_p2(&parsed_input)
+        );
+    }
+
+    #[test]
+    fn ocr() {
+        let rows = [
+            "#..#..###",
+            "#..#...#.",
+            "####...#.",
+            "#..#...#.",
+            "#..#...#.",
+            "#..#..###",
+        ];
+        let dots = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.bytes()
+                    .enumerate()
+                    .filter(|(_, pixel)| *pixel == b'#')
+                    .map(move |(x, _)| (x, y))
+            })
+            .collect::<super::Dots>();
+        assert_eq!(Ok("HI".to_owned()), super::read_letters(&dots));
+
+        let smudged = dots.iter().chain([(4, 2), (11, 0)]).collect();
+        assert_eq!(
+            Err(super::OcrError {
+                positions: vec![0, 2],
+                text: "?I?".to_owned(),
+            }),
+            super::read_letters(&smudged)
+        );
+        assert_eq!(
+            Ok(String::new()),
+            super::read_letters(&super::Dots::default())
+        );
+    }
+
+    #[test]
+    fn generator() {
+        let paper = super::generate("HELLO", 6, 2021).unwrap();
+        assert_eq!(6, paper.1.len());
+        assert!(paper.0.len() > 60);
+
+        let input = super::write_input(&paper);
+        let parsed_input = super::parse_input(&input);
+        assert_eq!(paper, parsed_input);
+        assert_eq!(Ok("HELLO".to_owned()), super::solve_p2(&parsed_input));
+
+        assert_eq!(None, super::generate("hello", 2, 1));
+    }
+
+    #[test]
+    fn asymmetric_folds() {
+        use super::{fold_once, Dots, Fold};
+
+        let input = "2,0\n3,0\n0,5\n\nfold along x=1\nfold along y=1";
+        let (dots, folds) = super::parse_input(input);
+        assert_eq!(vec![Fold::X(1), Fold::Y(1)], folds);
+
+        // the far side is wider than the near side, so the result shifts
+        let dots = fold_once(&dots, folds[0]);
+        assert_eq!(
+            vec![(0, 0), (1, 0), (1, 5)],
+            dots.iter().collect::<Vec<_>>()
+        );
+        let dots = fold_once(&dots, folds[1]);
+        assert_eq!(
+            vec![(0, 3), (1, 0), (1, 3)],
+            dots.iter().collect::<Vec<_>>()
+        );
+
+        let dots = [(1, 0), (3, 2)].into_iter().collect::<Dots>();
+        assert_eq!(
+            vec![(0, 2), (2, 0)],
+            fold_once(&dots, Fold::X(0)).iter().collect::<Vec<_>>()
+        );
+
+        let dots = [(1, 0), (3, 0), (2, 2)].into_iter().collect::<Dots>();
+        assert_eq!(
+            vec![(1, 0)],
+            fold_once(&dots, Fold::X(2)).iter().collect::<Vec<_>>()
+        );
     }
 }
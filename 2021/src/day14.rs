@@ -1,195 +1,499 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{self, Write},
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use itertools::Itertools;
+
+/// splits a formula into element symbols: an uppercase letter followed by any lowercase letters
+/// (`CaRnH` is `Ca`, `Rn`, `H`), or any other single char
+fn split_symbols(formula: &str) -> Vec<&str> {
+    let starts = formula
+        .char_indices()
+        .filter(|&(i, c)| i == 0 || !c.is_ascii_lowercase())
+        .map(|(i, _)| i)
+        .chain([formula.len()])
+        .collect::<Vec<_>>();
 
-type Polymer = (HashMap<u16, (u16, usize)>, HashMap<u16, usize>);
+    starts
+        .windows(2)
+        .map(|bounds| &formula[bounds[0]..bounds[1]])
+        .collect()
+}
 
-fn step(reactions: &mut HashMap<u16, (u16, usize)>, polymer: &mut HashMap<u16, usize>) {
-    reactions
-        .clone()
-        .iter()
-        .for_each(|(pair, (insert, count))| {
-            let first = pair & 0xff00 | *insert;
-            let second = pair & 0xff | *insert << 8;
-            reactions
-                .entry(*pair)
-                .and_modify(|record| record.1 -= count);
-            reactions
-                .entry(first)
-                .and_modify(|record| record.1 += count);
-            reactions
-                .entry(second)
-                .and_modify(|record| record.1 += count);
-
-            polymer
-                .entry(*insert)
-                .and_modify(|i| *i += count)
-                .or_insert(*count);
+/// polymer template and insertion rules over interned element symbols
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolymerModel {
+    symbols: Vec<String>,
+    template: Vec<usize>,
+    rules: HashMap<(usize, usize), usize>,
+}
+
+/// element counts (indexed by symbol id) and pair counts after some steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub elements: Vec<usize>,
+    pub pairs: HashMap<(usize, usize), usize>,
+}
+
+impl Snapshot {
+    /// most common minus least common element count
+    pub fn score(&self) -> usize {
+        let counts = self.elements.iter().filter(|&&count| count > 0);
+        counts.clone().max().unwrap_or(&0) - counts.min().unwrap_or(&0)
+    }
+}
+
+impl PolymerModel {
+    pub fn new(input: &str) -> Option<Self> {
+        let (template, rules) = input.split_once("\n\n")?;
+
+        let mut symbols = Vec::new();
+        let template = split_symbols(template.trim())
+            .into_iter()
+            .map(|symbol| Self::intern(&mut symbols, symbol))
+            .collect::<Vec<_>>();
+
+        let rules = rules
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (pair, insert) = line.split_once(" -> ")?;
+                match (
+                    &split_symbols(pair.trim())[..],
+                    &split_symbols(insert.trim())[..],
+                ) {
+                    ([first, second], [insert]) => Some((
+                        (
+                            Self::intern(&mut symbols, first),
+                            Self::intern(&mut symbols, second),
+                        ),
+                        Self::intern(&mut symbols, insert),
+                    )),
+                    _ => None,
+                }
+            })
+            .collect::<Option<_>>()?;
+
+        if template.is_empty() {
+            return None;
+        }
+
+        Some(PolymerModel {
+            symbols,
+            template,
+            rules,
+        })
+    }
+
+    fn intern(symbols: &mut Vec<String>, symbol: &str) -> usize {
+        symbols
+            .iter()
+            .position(|known| known == symbol)
+            .unwrap_or_else(|| {
+                symbols.push(symbol.to_owned());
+                symbols.len() - 1
+            })
+    }
+
+    /// element symbols in order of first appearance
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    pub fn initial(&self) -> Snapshot {
+        let mut elements = vec![0; self.symbols.len()];
+        self.template
+            .iter()
+            .for_each(|&element| elements[element] += 1);
+
+        let pairs = self
+            .template
+            .iter()
+            .tuple_windows()
+            .map(|(&first, &second)| (first, second))
+            .counts();
+
+        Snapshot { elements, pairs }
+    }
+
+    pub fn step(&self, state: &Snapshot) -> Snapshot {
+        let mut elements = state.elements.clone();
+        let mut pairs = HashMap::new();
+
+        state.pairs.iter().for_each(|(&(first, second), &count)| {
+            match self.rules.get(&(first, second)) {
+                Some(&insert) => {
+                    elements[insert] += count;
+                    *pairs.entry((first, insert)).or_insert(0) += count;
+                    *pairs.entry((insert, second)).or_insert(0) += count;
+                }
+                None => *pairs.entry((first, second)).or_insert(0) += count,
+            }
         });
+
+        Snapshot { elements, pairs }
+    }
+
+    /// snapshots for steps `0..=steps`
+    pub fn series(&self, steps: usize) -> Vec<Snapshot> {
+        let mut series = vec![self.initial()];
+        (0..steps).for_each(|_| series.push(self.step(series.last().unwrap())));
+        series
+    }
+
+    /// writes a series as csv rows `step,kind,symbol,count`. kind is `element` or `pair`, and
+    /// pair symbols are the two elements joined.
+    pub fn write_series_csv<W: Write>(&self, series: &[Snapshot], out: &mut W) -> io::Result<()> {
+        writeln!(out, "step,kind,symbol,count")?;
+        for (step, snapshot) in series.iter().enumerate() {
+            for (symbol, count) in self.symbols.iter().zip(&snapshot.elements) {
+                writeln!(out, "{},element,{},{}", step, symbol, count)?;
+            }
+
+            let pairs = snapshot
+                .pairs
+                .iter()
+                .filter(|(_, &count)| count > 0)
+                .map(|(&(first, second), count)| {
+                    (
+                        format!("{}{}", self.symbols[first], self.symbols[second]),
+                        count,
+                    )
+                })
+                .sorted();
+            for (symbol, count) in pairs {
+                writeln!(out, "{},pair,{},{}", step, symbol, count)?;
+            }
+        }
+        Ok(())
+    }
 }
 
-fn steps(
-    reactions: &mut HashMap<u16, (u16, usize)>,
-    polymer: &mut HashMap<u16, usize>,
-    steps: usize,
-) {
-    (0..steps).for_each(|_| {
-        step(reactions, polymer);
-    });
-}
-
-#[allow(unused)]
-fn print_polymer(polymer: &HashMap<u16, (u16, usize)>) {
-    polymer.iter().for_each(|(pair, (insert, count))| {
-        let bytes = pair.to_ne_bytes();
-        let insert = insert.to_ne_bytes()[0];
-
-        println!(
-            "{}{} => {}, {}",
-            char::from(bytes[0]),
-            char::from(bytes[1]),
-            char::from(insert),
-            count
-        );
-    });
+fn parse_input(input: &str) -> PolymerModel {
+    PolymerModel::new(input).expect("invalid polymer input")
 }
 
-fn parse_polymer(input: &str) -> Polymer {
-    let reactions =
-        input
-            .as_bytes()
-            .windows(2)
-            .fold(HashMap::<u16, (u16, usize)>::new(), |mut agg, input| {
-                let bytes = [input[0], input[1]];
-                agg.entry(u16::from_ne_bytes(bytes))
-                    .and_modify(|(_, count)| *count += 1)
-                    .or_insert((0, 1));
-                agg
-            });
+/// arithmetic used to count pairs when raising the insertion matrix to a power
+pub trait Counter {
+    type Value: Clone;
+
+    fn value(&self, count: usize) -> Self::Value;
+    fn add(&self, first: &Self::Value, second: &Self::Value) -> Self::Value;
+    fn mul(&self, first: &Self::Value, second: &Self::Value) -> Self::Value;
+}
 
-    let polymer = input.bytes().fold(HashMap::new(), |mut agg, i| {
-        agg.entry(i as u16).and_modify(|i| *i += 1).or_insert(1);
-        agg
-    });
+/// exact u128 counts, panicking on overflow (around step 120 for the puzzle)
+pub struct Wide;
 
-    (reactions, polymer)
+/// exact arbitrary-precision counts
+pub struct Big;
+
+/// counts modulo the given value
+pub struct Modular(pub u64);
+
+impl Counter for Wide {
+    type Value = u128;
+
+    fn value(&self, count: usize) -> u128 {
+        count as u128
+    }
+
+    fn add(&self, first: &u128, second: &u128) -> u128 {
+        first.checked_add(*second).expect("pair count overflow")
+    }
+
+    fn mul(&self, first: &u128, second: &u128) -> u128 {
+        first.checked_mul(*second).expect("pair count overflow")
+    }
 }
 
-fn parse_reactions(input: &str, reactions: &mut HashMap<u16, (u16, usize)>) {
-    input.lines().for_each(|line| {
-        if let Some((first, second)) = line.split_once(" -> ") {
-            let bytes = first.as_bytes();
-            let bytes = [bytes[0], bytes[1]];
-            let insert = second.as_bytes()[0] as u16;
-            reactions
-                .entry(u16::from_ne_bytes(bytes))
-                .and_modify(|(replace, _)| *replace = insert)
-                .or_insert((insert, 0));
-        }
-    });
+impl Counter for Big {
+    type Value = BigCount;
+
+    fn value(&self, count: usize) -> BigCount {
+        BigCount::from(count as u128)
+    }
+
+    fn add(&self, first: &BigCount, second: &BigCount) -> BigCount {
+        first.add(second)
+    }
+
+    fn mul(&self, first: &BigCount, second: &BigCount) -> BigCount {
+        first.mul(second)
+    }
 }
 
-fn parse_input(input: &str) -> Polymer {
-    if let Some((first, rest)) = input.split_once("\n\n") {
-        let (mut reactions, polymer) = parse_polymer(first);
+impl Counter for Modular {
+    type Value = u64;
 
-        parse_reactions(rest, &mut reactions);
+    fn value(&self, count: usize) -> u64 {
+        (count as u128 % self.0 as u128) as u64
+    }
+
+    fn add(&self, first: &u64, second: &u64) -> u64 {
+        ((*first as u128 + *second as u128) % self.0 as u128) as u64
+    }
 
-        (reactions, polymer)
-    } else {
-        unreachable!();
+    fn mul(&self, first: &u64, second: &u64) -> u64 {
+        ((*first as u128 * *second as u128) % self.0 as u128) as u64
     }
 }
 
-#[allow(unused)]
-fn equal_reactions(
-    first: &HashMap<u16, (u16, usize)>,
-    second: &HashMap<u16, (u16, usize)>,
-) -> bool {
-    let mut first_vec = first
-        .iter()
-        .filter(|(_, (_, count))| *count != 0)
-        .map(|(&pair, &(_, count))| (pair, count))
-        .collect::<Vec<_>>();
-    let mut second_vec = second
-        .iter()
-        .filter(|(_, (_, count))| *count != 0)
-        .map(|(&pair, &(_, count))| (pair, count))
-        .collect::<Vec<_>>();
+/// unsigned big integer stored as little endian base 2^32 limbs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigCount {
+    limbs: Vec<u32>,
+}
 
-    first_vec.sort_unstable();
-    second_vec.sort_unstable();
+impl BigCount {
+    fn trim(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        self
+    }
 
-    first_vec == second_vec
+    fn add(&self, other: &Self) -> Self {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let sum = carry
+                + *self.limbs.get(i).unwrap_or(&0) as u64
+                + *other.limbs.get(i).unwrap_or(&0) as u64;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+
+        BigCount { limbs }.trim()
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &first) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &second) in other.limbs.iter().enumerate() {
+                let product = first as u64 * second as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+
+        BigCount { limbs }.trim()
+    }
+
+    /// divides in place by a small divisor, returning the remainder
+    fn div_rem(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let current = remainder << 32 | *limb as u64;
+            *limb = (current / divisor as u64) as u32;
+            remainder = current % divisor as u64;
+        }
+        remainder as u32
+    }
 }
 
-#[allow(unused)]
-fn equal_polymers(first: &HashMap<u16, usize>, second: &HashMap<u16, usize>) -> bool {
-    let mut first_vec = first
-        .iter()
-        .filter(|(_, &count)| count != 0)
-        .map(|(&pair, &count)| (pair, count))
-        .collect::<Vec<_>>();
-    let mut second_vec = second
-        .iter()
-        .filter(|(_, &count)| count != 0)
-        .map(|(&pair, &count)| (pair, count))
-        .collect::<Vec<_>>();
+impl From<u128> for BigCount {
+    fn from(value: u128) -> Self {
+        BigCount {
+            limbs: (0..4).map(|i| (value >> (32 * i)) as u32).collect(),
+        }
+        .trim()
+    }
+}
 
-    first_vec.sort_unstable();
-    second_vec.sort_unstable();
+impl Display for BigCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.clone();
+        let mut chunks = Vec::new();
+        while !value.limbs.is_empty() {
+            chunks.push(value.div_rem(1_000_000_000));
+            value = value.trim();
+        }
 
-    first_vec == second_vec
+        match chunks.split_last() {
+            None => write!(f, "0"),
+            Some((most, rest)) => {
+                write!(f, "{}", most)?;
+                rest.iter()
+                    .rev()
+                    .try_for_each(|chunk| write!(f, "{:09}", chunk))
+            }
+        }
+    }
 }
 
-fn polymer_score(polymer: &HashMap<u16, usize>) -> usize {
-    let mut chars = polymer
-        .iter()
-        .map(|(char, count)| (*count, *char))
-        .collect::<Vec<_>>();
+/// pair insertion as a linear map over pair counts, so k steps become the kth power of the
+/// transition matrix, computed by repeated squaring
+#[derive(Debug, Clone)]
+pub struct InsertionMatrix {
+    symbols: Vec<String>,
+    /// initial count of each pair, indexed `first * symbols.len() + second`
+    pairs: Vec<usize>,
+    /// index of the template's last element, which insertion never moves
+    last: usize,
+    /// the pairs each pair turns into after one step
+    produces: Vec<Vec<usize>>,
+}
+
+impl InsertionMatrix {
+    pub fn new(input: &str) -> Option<Self> {
+        PolymerModel::new(input).as_ref().map(Self::from)
+    }
+
+    /// transition matrix where `t[to][from]` counts the `to` pairs one `from` pair becomes after
+    /// a step
+    fn transitions<C: Counter>(&self, counter: &C) -> Vec<Vec<C::Value>> {
+        let mut counts = vec![vec![0; self.pairs.len()]; self.pairs.len()];
+        self.produces
+            .iter()
+            .enumerate()
+            .for_each(|(from, produced)| {
+                produced.iter().for_each(|&to| counts[to][from] += 1);
+            });
+
+        counts
+            .iter()
+            .map(|row| row.iter().map(|&count| counter.value(count)).collect())
+            .collect()
+    }
 
-    chars.sort_unstable();
+    /// pair counts after `steps` insertion steps
+    pub fn pair_counts<C: Counter>(&self, counter: &C, steps: u64) -> Vec<C::Value> {
+        let mut matrix = self.transitions(counter);
+        let mut counts = self
+            .pairs
+            .iter()
+            .map(|&count| counter.value(count))
+            .collect::<Vec<_>>();
+
+        let mut remaining = steps;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                counts = mat_vec(counter, &matrix, &counts);
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                matrix = mat_mul(counter, &matrix, &matrix);
+            }
+        }
+
+        counts
+    }
 
-    let least = chars.first().unwrap();
-    let most = chars.last().unwrap();
+    /// element counts after `steps` insertion steps, in symbol order
+    pub fn element_counts<C: Counter>(&self, counter: &C, steps: u64) -> Vec<(&str, C::Value)> {
+        let size = self.symbols.len();
+        let mut elements = vec![counter.value(0); size];
+        elements[self.last] = counter.value(1);
+
+        self.pair_counts(counter, steps)
+            .iter()
+            .enumerate()
+            .for_each(|(pair, count)| {
+                elements[pair / size] = counter.add(&elements[pair / size], count);
+            });
 
-    most.0 - least.0
+        self.symbols
+            .iter()
+            .map(String::as_str)
+            .zip(elements)
+            .collect()
+    }
 }
 
-fn solve_p1((reactions, polymer): &Polymer) -> usize {
-    let mut polymer = polymer.clone();
-    let mut reactions = reactions.clone();
+impl From<&PolymerModel> for InsertionMatrix {
+    fn from(model: &PolymerModel) -> Self {
+        let size = model.symbols.len();
 
-    steps(&mut reactions, &mut polymer, 10);
+        let mut pairs = vec![0; size * size];
+        model
+            .template
+            .windows(2)
+            .for_each(|pair| pairs[pair[0] * size + pair[1]] += 1);
 
-    // print_polymer(&reactions);
+        let mut produces = (0..size * size).map(|pair| vec![pair]).collect::<Vec<_>>();
+        model.rules.iter().for_each(|(&(first, second), &insert)| {
+            produces[first * size + second] = vec![first * size + insert, insert * size + second];
+        });
 
-    polymer_score(&polymer)
+        InsertionMatrix {
+            symbols: model.symbols.clone(),
+            pairs,
+            last: *model.template.last().unwrap(),
+            produces,
+        }
+    }
 }
 
-fn solve_p2((reactions, polymer): &Polymer) -> usize {
-    let mut polymer = polymer.clone();
-    let mut reactions = reactions.clone();
+fn mat_vec<C: Counter>(
+    counter: &C,
+    matrix: &[Vec<C::Value>],
+    vector: &[C::Value],
+) -> Vec<C::Value> {
+    matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(vector)
+                .fold(counter.value(0), |sum, (first, second)| {
+                    counter.add(&sum, &counter.mul(first, second))
+                })
+        })
+        .collect()
+}
 
-    steps(&mut reactions, &mut polymer, 40);
+fn mat_mul<C: Counter>(
+    counter: &C,
+    first: &[Vec<C::Value>],
+    second: &[Vec<C::Value>],
+) -> Vec<Vec<C::Value>> {
+    let size = second.len();
+    first
+        .iter()
+        .map(|row| {
+            (0..size)
+                .map(|col| {
+                    row.iter()
+                        .zip(second)
+                        .fold(counter.value(0), |sum, (value, other)| {
+                            counter.add(&sum, &counter.mul(value, &other[col]))
+                        })
+                })
+                .collect()
+        })
+        .collect()
+}
 
-    // print_polymer(&reactions);
+fn solve_p1(model: &PolymerModel) -> usize {
+    (0..10)
+        .fold(model.initial(), |state, _| model.step(&state))
+        .score()
+}
 
-    polymer_score(&polymer)
+fn solve_p2(model: &PolymerModel) -> usize {
+    (0..40)
+        .fold(model.initial(), |state, _| model.step(&state))
+        .score()
 }
 
 #[aoc_generator(day14)]
-pub fn input_generator(input: &str) -> Polymer {
+pub fn input_generator(input: &str) -> PolymerModel {
     parse_input(input)
 }
 
 #[aoc(day14, part1)]
-pub fn wrapper_p1(input: &Polymer) -> usize {
+pub fn wrapper_p1(input: &PolymerModel) -> usize {
     solve_p1(input)
 }
 
 #[aoc(day14, part2)]
-pub fn wrapper_p2(input: &Polymer) -> usize {
+pub fn wrapper_p2(input: &PolymerModel) -> usize {
     solve_p2(input)
 }
 
@@ -200,40 +504,123 @@ mod tests {
     fn it_works() {
         let input = "NNCB\n\nCH -> B\nHH -> N\nCB -> H\nNH -> C\nHB -> C\nHC -> B\nHN -> C\nNN -> C\nBH -> H\nNC -> B\nNB -> B\nBN -> B\nBB -> N\nBC -> B\nCC -> N\nCN -> C";
 
-        let (parsed_reactions, parsed_polymer) = super::parse_input(input);
-        println!("{:?} => {:?}", parsed_polymer, &parsed_reactions);
-
-        let tests = vec![
+        let model = super::parse_input(input);
+        let rules = input.split_once("\n\n").unwrap().1;
+
+        let named = |model: &super::PolymerModel, snapshot: &super::Snapshot| {
+            let symbols = model.symbols();
+            let mut elements = symbols
+                .iter()
+                .zip(&snapshot.elements)
+                .filter(|(_, &count)| count > 0)
+                .map(|(symbol, &count)| (symbol.clone(), count))
+                .collect::<Vec<_>>();
+            let mut pairs = snapshot
+                .pairs
+                .iter()
+                .filter(|(_, &count)| count > 0)
+                .map(|(&(first, second), &count)| {
+                    (format!("{}{}", symbols[first], symbols[second]), count)
+                })
+                .collect::<Vec<_>>();
+            elements.sort_unstable();
+            pairs.sort_unstable();
+            (elements, pairs)
+        };
+
+        let tests = [
             (1usize, "NCNBCHB"),
             (2usize, "NBCCNBBBCBHCB"),
             (3usize, "NBBBCNCCNBBNBNBBCHBHHBCHB"),
             (4usize, "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB"),
         ];
 
+        let series = model.series(4);
         tests.iter().for_each(|&(steps, polymer)| {
-            let (target_reactions, target_polymer) = super::parse_polymer(polymer);
+            let target = super::parse_input(&format!("{}\n\n{}", polymer, rules));
 
-            let mut parsed_polymer = parsed_polymer.clone();
-            let mut parsed_reactions = parsed_reactions.clone();
-            super::steps(&mut parsed_reactions, &mut parsed_polymer, steps);
+            assert_eq!(
+                named(&target, &target.initial()),
+                named(&model, &series[steps])
+            );
+        });
 
-            // println!("after {} steps:", steps);
-            // super::print_polymer(&parsed_input);
-            // println!("expected:");
-            // super::print_polymer(&target_polymer);
+        assert_eq!(1588, super::solve_p1(&model));
+        assert_eq!(2188189693529, super::solve_p2(&model));
+    }
 
-            // println!("step: {}, expect: {}", steps, polymer);
-            assert!(super::equal_reactions(&target_reactions, &parsed_reactions));
-            assert!(super::equal_polymers(&target_polymer, &parsed_polymer));
-        });
+    #[test]
+    fn symbols_and_series() {
+        let model = super::parse_input("CaRnCa\n\nCaRn -> H\nRnCa -> Ca\nCaH -> Rn");
+        assert_eq!(model.symbols(), ["Ca", "Rn", "H"]);
 
+        let series = model.series(2);
+        assert_eq!(vec![2, 1, 0], series[0].elements);
+        assert_eq!(vec![3, 1, 1], series[1].elements);
+        assert_eq!(vec![4, 2, 1], series[2].elements);
+        assert_eq!(Some(&1), series[2].pairs.get(&(0, 1)));
+
+        let matrix = super::InsertionMatrix::from(&model);
         assert_eq!(
-            1588,
-            super::solve_p1(&(parsed_reactions.clone(), parsed_polymer.clone()))
+            vec![("Ca", 4u128), ("Rn", 2), ("H", 1)],
+            matrix.element_counts(&super::Wide, 2)
         );
+
+        let mut csv = Vec::new();
+        model.write_series_csv(&series[..2], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
         assert_eq!(
-            2188189693529,
-            super::solve_p2(&(parsed_reactions.clone(), parsed_polymer.clone()))
+            csv,
+            "step,kind,symbol,count\n\
+             0,element,Ca,2\n0,element,Rn,1\n0,element,H,0\n\
+             0,pair,CaRn,1\n0,pair,RnCa,1\n\
+             1,element,Ca,3\n1,element,Rn,1\n1,element,H,1\n\
+             1,pair,CaCa,1\n1,pair,CaH,1\n1,pair,HRn,1\n1,pair,RnCa,1\n"
         );
     }
+
+    #[test]
+    fn matrix_power() {
+        use super::{Big, BigCount, InsertionMatrix, Modular, Wide};
+
+        let input = "NNCB\n\nCH -> B\nHH -> N\nCB -> H\nNH -> C\nHB -> C\nHC -> B\nHN -> C\nNN -> C\nBH -> H\nNC -> B\nNB -> B\nBN -> B\nBB -> N\nBC -> B\nCC -> N\nCN -> C";
+        let matrix = InsertionMatrix::new(input).unwrap();
+
+        let score = |steps| {
+            let counts = matrix.element_counts(&Wide, steps);
+            let most = counts.iter().map(|&(_, count)| count).max().unwrap();
+            let least = counts.iter().map(|&(_, count)| count).min().unwrap();
+            most - least
+        };
+        assert_eq!(1588, score(10));
+        assert_eq!(2188189693529, score(40));
+
+        let wide = matrix.element_counts(&Wide, 100);
+        let big = matrix.element_counts(&Big, 100);
+        let prime = 1_000_000_007;
+        let modular = matrix.element_counts(&Modular(prime), 100);
+        for ((wide, big), modular) in wide.iter().zip(&big).zip(&modular) {
+            assert_eq!(BigCount::from(wide.1), big.1);
+            assert_eq!(wide.1.to_string(), big.1.to_string());
+            assert_eq!((wide.1 % prime as u128) as u64, modular.1);
+        }
+
+        // the polymer length after k steps is (len - 1) * 2^k + 1
+        let steps = 1_000_000_000_000u64;
+        let total = matrix
+            .element_counts(&Modular(prime), steps)
+            .iter()
+            .fold(0, |sum, &(_, count)| (sum + count) % prime);
+        let mut power = 1u64;
+        let mut base = 2u64;
+        let mut exponent = steps;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                power = power * base % prime;
+            }
+            base = base * base % prime;
+            exponent >>= 1;
+        }
+        assert_eq!((3 * power + 1) % prime, total);
+    }
 }
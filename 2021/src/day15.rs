@@ -1,7 +1,8 @@
 use std::{cmp::Ordering, collections::BinaryHeap};
 
 use aoc_runner_derive::{aoc, aoc_generator};
-use ndarray::{Array2, Axis};
+use itertools::Itertools;
+use ndarray::Array2;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 struct State {
@@ -24,6 +25,52 @@ impl PartialOrd for State {
     }
 }
 
+/// how the cave is built from the scanned map and how we may move through it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaveRules {
+    /// copies of the map along each axis
+    pub tiling: usize,
+    /// added to the risk for every tile to the right or down
+    pub tile_increment: usize,
+    /// risk runs 1..=max_risk and wraps back around to 1
+    pub max_risk: usize,
+    /// allow moving diagonally as well as orthogonally
+    pub diagonal: bool,
+    /// stop as soon as the goal is reached, searching towards it with an a* heuristic
+    pub a_star: bool,
+}
+
+impl Default for CaveRules {
+    fn default() -> Self {
+        CaveRules {
+            tiling: 1,
+            tile_increment: 1,
+            max_risk: 9,
+            diagonal: false,
+            a_star: false,
+        }
+    }
+}
+
+impl CaveRules {
+    /// a lower bound on the risk from coord to goal, as every step costs at least 1. that is the
+    /// manhattan distance, or the chebyshev distance when diagonal steps cover two axes at once.
+    fn heuristic(&self, coord: &(usize, usize), goal: &(usize, usize)) -> usize {
+        if !self.a_star {
+            return 0;
+        }
+
+        let rows = coord.0.abs_diff(goal.0);
+        let cols = coord.1.abs_diff(goal.1);
+
+        if self.diagonal {
+            rows.max(cols)
+        } else {
+            rows + cols
+        }
+    }
+}
+
 trait Coord {
     fn convert(&self, shape: &[usize]) -> usize;
     fn from(coord: usize, shape: &[usize]) -> Self;
@@ -31,65 +78,72 @@ trait Coord {
 
 impl Coord for (usize, usize) {
     fn convert(&self, shape: &[usize]) -> usize {
-        self.0 * shape[0] + self.1
+        self.0 * shape[1] + self.1
     }
 
     fn from(coord: usize, shape: &[usize]) -> Self {
-        (coord / shape[0], coord % shape[0])
+        (coord / shape[1], coord % shape[1])
     }
 }
 
-fn nearby(grid: &Array2<usize>, coord: &(usize, usize)) -> Vec<(usize, usize)> {
+fn nearby(grid: &Array2<usize>, coord: &(usize, usize), diagonal: bool) -> Vec<(usize, usize)> {
     let shape = grid.shape();
-    let mut possibles = vec![];
-
-    if coord.0 != 0 {
-        possibles.push((coord.0 - 1, coord.1));
-    }
+    let (row, col) = (coord.0 as isize, coord.1 as isize);
 
-    if coord.0 + 1 < shape[0] {
-        possibles.push((coord.0 + 1, coord.1));
+    let mut offsets = vec![(-1, 0), (1, 0), (0, -1), (0, 1)];
+    if diagonal {
+        offsets.extend([(-1, -1), (-1, 1), (1, -1), (1, 1)]);
     }
 
-    if coord.1 != 0 {
-        possibles.push((coord.0, coord.1 - 1));
-    }
-
-    if coord.1 + 1 < shape[1] {
-        possibles.push((coord.0, coord.1 + 1));
-    }
-
-    possibles
+    offsets
+        .into_iter()
+        .map(|(dr, dc)| (row + dr, col + dc))
+        .filter(|(row, col)| {
+            (0..shape[0] as isize).contains(row) && (0..shape[1] as isize).contains(col)
+        })
+        .map(|(row, col)| (row as usize, col as usize))
+        .collect()
 }
 
-fn dijkstra(grid: &Array2<usize>, start: &(usize, usize)) -> (Vec<usize>, Vec<usize>) {
+fn dijkstra(
+    grid: &Array2<usize>,
+    start: &(usize, usize),
+    goal: &(usize, usize),
+    rules: &CaveRules,
+) -> (Vec<usize>, Vec<usize>) {
     let shape = grid.shape();
     let mut dist = (0..grid.len()).map(|_| usize::MAX).collect::<Vec<_>>();
     let mut prev = (0..grid.len()).map(|_| usize::MAX).collect::<Vec<_>>();
 
     let mut queue = BinaryHeap::new();
 
+    // queued costs include the heuristic, which is always 0 for plain dijkstra
     dist[start.convert(shape)] = 0;
     queue.push(State {
-        cost: 0,
+        cost: rules.heuristic(start, goal),
         position: *start,
     });
 
     while let Some(State { cost, position }) = queue.pop() {
-        if cost > dist[position.convert(shape)] {
+        let risk = dist[position.convert(shape)];
+        if cost > risk + rules.heuristic(&position, goal) {
             continue;
         }
 
-        for edge in nearby(grid, &position) {
-            let next = State {
-                cost: cost + grid[edge],
-                position: edge,
-            };
+        if rules.a_star && position == *goal {
+            break;
+        }
+
+        for edge in nearby(grid, &position, rules.diagonal) {
+            let next_risk = risk + grid[edge];
 
-            if next.cost < dist[edge.convert(shape)] {
-                queue.push(next);
+            if next_risk < dist[edge.convert(shape)] {
+                queue.push(State {
+                    cost: next_risk + rules.heuristic(&edge, goal),
+                    position: edge,
+                });
 
-                dist[edge.convert(shape)] = next.cost;
+                dist[edge.convert(shape)] = next_risk;
                 prev[edge.convert(shape)] = position.convert(shape);
             }
         }
@@ -98,7 +152,6 @@ fn dijkstra(grid: &Array2<usize>, start: &(usize, usize)) -> (Vec<usize>, Vec<us
     (dist, prev)
 }
 
-#[allow(unused)]
 fn rebuild_path(prev: Vec<usize>, start: usize, goal: usize) -> Vec<usize> {
     let mut coord = goal;
     let mut path = Vec::new();
@@ -115,38 +168,15 @@ fn rebuild_path(prev: Vec<usize>, start: usize, goal: usize) -> Vec<usize> {
     path
 }
 
-fn expand(grid: &Array2<usize>) -> Array2<usize> {
-    let mut space = grid.clone();
-
-    space
-        .append(Axis(1), ((grid + 1 - 1) % 9 + 1).view())
-        .unwrap();
-    space
-        .append(Axis(1), ((grid + 2 - 1) % 9 + 1).view())
-        .unwrap();
-    space
-        .append(Axis(1), ((grid + 3 - 1) % 9 + 1).view())
-        .unwrap();
-    space
-        .append(Axis(1), ((grid + 4 - 1) % 9 + 1).view())
-        .unwrap();
-
-    let mut galaxy = space.clone();
-
-    galaxy
-        .append(Axis(0), ((&space + 1 - 1) % 9 + 1).view())
-        .unwrap();
-    galaxy
-        .append(Axis(0), ((&space + 2 - 1) % 9 + 1).view())
-        .unwrap();
-    galaxy
-        .append(Axis(0), ((&space + 3 - 1) % 9 + 1).view())
-        .unwrap();
-    galaxy
-        .append(Axis(0), ((&space + 4 - 1) % 9 + 1).view())
-        .unwrap();
-
-    galaxy
+/// the full cave, with the scanned map tiled out according to the rules
+pub fn expand(grid: &Array2<usize>, rules: &CaveRules) -> Array2<usize> {
+    let (rows, cols) = grid.dim();
+
+    Array2::from_shape_fn((rows * rules.tiling, cols * rules.tiling), |(row, col)| {
+        let tile = row / rows + col / cols;
+        let risk = grid[(row % rows, col % cols)] + tile * rules.tile_increment;
+        (risk - 1) % rules.max_risk + 1
+    })
 }
 
 fn parse_input(input: &str) -> Array2<usize> {
@@ -160,43 +190,60 @@ fn parse_input(input: &str) -> Array2<usize> {
     Array2::from_shape_vec((rows, cols), bytes).unwrap()
 }
 
-fn solve_p1(input: &Array2<usize>) -> usize {
+/// the lowest total risk from the top left to the bottom right of the cave
+pub fn lowest_risk(input: &Array2<usize>, rules: &CaveRules) -> usize {
+    let input = expand(input, rules);
     let shape = input.shape();
 
     let start = &(0, 0);
     let goal = &(shape[0] - 1, shape[1] - 1);
 
-    let (dist, _) = dijkstra(input, start);
-
-    // println!(
-    //     "{:?}",
-    //     rebuild_path(prev, start.convert(shape), goal.convert(shape))
-    //         .iter()
-    //         .map(|i| <(usize, usize) as Coord>::from(*i, shape))
-    //         .collect::<Vec<_>>()
-    // );
+    let (dist, _) = dijkstra(&input, start, goal, rules);
 
     dist[goal.convert(shape)]
 }
 
-fn solve_p2(input: &Array2<usize>) -> usize {
-    let input = expand(input);
+/// the lowest risk route through the expanded cave from the top left to the bottom right, as
+/// each (row, col) visited along with the total risk on entering it
+pub fn lowest_risk_path(input: &Array2<usize>, rules: &CaveRules) -> Vec<((usize, usize), usize)> {
+    let input = expand(input, rules);
     let shape = input.shape();
 
     let start = &(0, 0);
     let goal = &(shape[0] - 1, shape[1] - 1);
 
-    let (dist, _) = dijkstra(&input, start);
+    let (dist, prev) = dijkstra(&input, start, goal, rules);
+
+    rebuild_path(prev, start.convert(shape), goal.convert(shape))
+        .into_iter()
+        .map(|i| (<(usize, usize) as Coord>::from(i, shape), dist[i]))
+        .collect()
+}
 
-    // println!(
-    //     "{:?}",
-    //     rebuild_path(prev, start.convert(shape), goal.convert(shape))
-    //         .iter()
-    //         .map(|i| <(usize, usize) as Coord>::from(*i, shape))
-    //         .collect::<Vec<_>>()
-    // );
+/// draws the cave with every cell on the path replaced by `*`
+pub fn render_path(cave: &Array2<usize>, path: &[((usize, usize), usize)]) -> String {
+    let mut rendered = cave.map(|risk| char::from_digit(*risk as u32, 36).unwrap_or('?'));
 
-    dist[goal.convert(shape)]
+    path.iter().for_each(|(coord, _)| rendered[*coord] = '*');
+
+    rendered
+        .rows()
+        .into_iter()
+        .map(|row| row.iter().collect::<String>())
+        .join("\n")
+}
+
+fn solve_p1(input: &Array2<usize>) -> usize {
+    lowest_risk(input, &CaveRules::default())
+}
+
+fn solve_p2(input: &Array2<usize>) -> usize {
+    let rules = CaveRules {
+        tiling: 5,
+        ..Default::default()
+    };
+
+    lowest_risk(input, &rules)
 }
 
 #[aoc_generator(day15)]
@@ -225,5 +272,73 @@ mod tests {
 
         assert_eq!(40, super::solve_p1(&parsed_input));
         assert_eq!(315, super::solve_p2(&parsed_input));
+
+        let rules = super::CaveRules {
+            a_star: true,
+            ..Default::default()
+        };
+        assert_eq!(40, super::lowest_risk(&parsed_input, &rules));
+
+        let rules = super::CaveRules { tiling: 5, ..rules };
+        assert_eq!(315, super::lowest_risk(&parsed_input, &rules));
+    }
+
+    #[test]
+    fn path() {
+        let grid = super::parse_input("199\n119\n911");
+        let path = super::lowest_risk_path(&grid, &Default::default());
+
+        assert_eq!(
+            vec![
+                ((0, 0), 0),
+                ((1, 0), 1),
+                ((1, 1), 2),
+                ((2, 1), 3),
+                ((2, 2), 4)
+            ],
+            path
+        );
+        assert_eq!("*99\n**9\n9**", super::render_path(&grid, &path));
+
+        let input = "1163751742\n1381373672\n2136511328\n3694931569\n7463417111\n1319128137\n1359912421\n3125421639\n1293138521\n2311944581";
+        let grid = super::parse_input(input);
+        let rules = super::CaveRules {
+            tiling: 5,
+            a_star: true,
+            ..Default::default()
+        };
+        let path = super::lowest_risk_path(&grid, &rules);
+        assert_eq!(Some(&((49, 49), 315)), path.last());
+        assert_eq!(
+            50 * 50 - path.len(),
+            super::render_path(&super::expand(&grid, &rules), &path)
+                .matches(char::is_numeric)
+                .count()
+        );
+    }
+
+    #[test]
+    fn custom_rules() {
+        let rules = super::CaveRules {
+            tiling: 3,
+            ..Default::default()
+        };
+        let expanded = super::expand(&super::parse_input("8"), &rules);
+        assert_eq!(vec![8, 9, 1, 9, 1, 2, 1, 2, 3], expanded.into_raw_vec());
+
+        let grid = super::parse_input("199\n919\n991");
+        assert_eq!(20, super::lowest_risk(&grid, &Default::default()));
+
+        let rules = super::CaveRules {
+            diagonal: true,
+            ..Default::default()
+        };
+        assert_eq!(2, super::lowest_risk(&grid, &rules));
+
+        let rules = super::CaveRules {
+            a_star: true,
+            ..rules
+        };
+        assert_eq!(2, super::lowest_risk(&grid, &rules));
     }
 }
@@ -1,4 +1,8 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io::{self, Write},
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
@@ -6,6 +10,7 @@ use lazy_static::lazy_static;
 use nalgebra::{matrix, Matrix3, Vector3};
 use ndarray::Array2;
 use pathfinding::prelude::kruskal_indices;
+use rayon::prelude::*;
 
 #[derive(Default, Clone, PartialEq)]
 pub struct Scanner {
@@ -13,7 +18,7 @@ pub struct Scanner {
     position: Option<Vector3<isize>>,
     rotation: Option<Matrix3<isize>>,
     beacons: Vec<Beacon>,
-    beacon_graph: Array2<isize>,
+    fingerprints: HashMap<Fingerprint, Vec<[usize; 3]>>,
 }
 
 impl Debug for Scanner {
@@ -115,83 +120,48 @@ lazy_static! {
     ];
 }
 
-fn counter_intersection(
-    first: &HashMap<Vec<isize>, usize>,
-    second: &HashMap<Vec<isize>, usize>,
-) -> usize {
-    first
-        .iter()
-        .filter_map(|(key, count)| second.get(key).map(|other_count| other_count.min(count)))
-        .sum()
-}
-
-fn beacon_graph(scanner: &Scanner) -> Array2<Vec<isize>> {
-    let beacon_count = scanner.beacons.len();
-
-    Array2::from_shape_fn((beacon_count, beacon_count), |(i, j)| {
-        let first = &scanner.beacons[i];
-        let second = &scanner.beacons[j];
-        taxicab_distance(first, second)
-    })
-}
-
-fn reposition(scanner: &Scanner, stable: &Scanner, threshold: usize) -> Option<Scanner> {
-    // println!(
-    //     "beginning reposition search {} based off {} on rotation {}",
-    //     scanner.id, stable.id, scanner.rotation.unwrap()
-    // );
-
-    let first_beacon_graph = beacon_graph(scanner);
-
-    let second_beacon_graph = beacon_graph(stable);
-
-    for (beacon_id, row) in first_beacon_graph.rows().into_iter().enumerate() {
-        let counter = row.into_iter().fold(HashMap::new(), |mut map, val| {
-            map.entry(val.clone()).and_modify(|v| *v += 1).or_insert(1);
-            map
-        });
-
-        for (other_beacon_id, other_row) in second_beacon_graph.rows().into_iter().enumerate() {
-            let other_counter = other_row.into_iter().fold(HashMap::new(), |mut map, val| {
-                map.entry(val.clone()).and_modify(|v| *v += 1).or_insert(1);
-                map
-            });
-
-            let intersection = counter_intersection(&counter, &other_counter);
-            // println!(
-            //     "orientation? {}/{} -> {}",
-            //     scanner.id, stable.id, intersection
-            // );
-        
-
-            if intersection >= threshold {
-                // we have found a match
-                // scanner.position + beacon.offset = stable.position + other_beacon.offset
-                // scanner.position = stable.position + other_beacon.offset - beacon.offset
-                let mut scanner = scanner.clone();
-                scanner.position = stable.position.map(|position| {
-                    position + stable.beacons[other_beacon_id].offset
-                        - scanner.beacons[beacon_id].offset
-                });
-                // println!("  found orientation! {:?}", scanner.position);
-                // let beacons = scanner
-                //     .beacons
-                //     .iter()
-                //     .map(|beacon| {
-                //         scanner
-                //             .position
-                //             .map(|position| position + beacon.offset)
-                //             .map(|vec| (vec[0], vec[1], vec[2]))
-                //             .unwrap_or_default()
-                //     })
-                //     .collect::<Vec<_>>();
-                // println!("  {:?}", beacons);
-                return Some(scanner);
+/// side lengths (squared) of a beacon triangle, sorted ascending
+type Fingerprint = [isize; 3];
+
+/// indexes every scalene triangle of beacons by its side lengths. the beacon indices are ordered
+/// by the length of the side opposite them, so matching triangles also give matching beacons.
+fn generate_fingerprints(beacon_graph: &Array2<isize>) -> HashMap<Fingerprint, Vec<[usize; 3]>> {
+    let beacon_count = beacon_graph.nrows();
+
+    (0..beacon_count)
+        .tuple_combinations()
+        .filter_map(|(i, j, k)| {
+            let mut sides = [
+                (beacon_graph[(j, k)], i),
+                (beacon_graph[(i, k)], j),
+                (beacon_graph[(i, j)], k),
+            ];
+            sides.sort_unstable();
+
+            // vertex order is ambiguous when two sides match
+            if sides[0].0 == sides[1].0 || sides[1].0 == sides[2].0 {
+                None
+            } else {
+                Some((
+                    [sides[0].0, sides[1].0, sides[2].0],
+                    [sides[0].1, sides[1].1, sides[2].1],
+                ))
             }
-        }
-    }
+        })
+        .fold(HashMap::new(), |mut map, (fingerprint, triangle)| {
+            map.entry(fingerprint)
+                .or_insert_with(Vec::new)
+                .push(triangle);
+            map
+        })
+}
 
-    None
+fn absolute_beacons(scanner: &Scanner) -> HashSet<Vector3<isize>> {
+    scanner
+        .beacons
+        .iter()
+        .filter_map(|beacon| scanner.position.map(|position| position + beacon.offset))
+        .collect()
 }
 
 fn reorient(scanner: &Scanner, orientation: &Matrix3<isize>) -> Scanner {
@@ -210,7 +180,7 @@ fn reorient(scanner: &Scanner, orientation: &Matrix3<isize>) -> Scanner {
                 }
             })
             .collect(),
-        beacon_graph: scanner.beacon_graph.clone(),
+        fingerprints: scanner.fingerprints.clone(),
     }
 }
 
@@ -219,11 +189,184 @@ fn find_right_orientation(
     target: &Scanner,
     threshold: usize,
 ) -> Option<Scanner> {
-    ORIENTATIONS
+    let stable_beacons = absolute_beacons(target);
+    let target_position = target.position?;
+    let mut tried = HashSet::new();
+
+    for (fingerprint, triangles) in &scanner.fingerprints {
+        let other_triangles = match target.fingerprints.get(fingerprint) {
+            Some(other_triangles) => other_triangles,
+            None => continue,
+        };
+
+        for (triangle, other_triangle) in triangles.iter().cartesian_product(other_triangles) {
+            let [b0, b1, b2] = triangle.map(|i| scanner.beacons[i].offset);
+            let [a0, a1, a2] = other_triangle.map(|i| target.beacons[i].offset);
+
+            let orientation = ORIENTATIONS.iter().find(|orientation| {
+                *orientation * (b1 - b0) == a1 - a0 && *orientation * (b2 - b0) == a2 - a0
+            });
+
+            if let Some(orientation) = orientation {
+                // scanner.position + orientation * b0 = target.position + a0
+                let position = target_position + a0 - orientation * b0;
+
+                if !tried.insert((*orientation, position)) {
+                    continue;
+                }
+
+                let overlap = scanner
+                    .beacons
+                    .iter()
+                    .filter(|beacon| {
+                        stable_beacons.contains(&(position + orientation * beacon.offset))
+                    })
+                    .count();
+
+                if overlap >= threshold {
+                    let mut scanner = reorient(scanner, orientation);
+                    scanner.position = Some(position);
+                    return Some(scanner);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// largest per-axis distance at which two beacons are considered the same
+    pub position: isize,
+    /// share of the smaller scanner's beacons that must agree with a transform to accept it
+    pub min_inlier_ratio: f64,
+}
+
+fn within(first: &Vector3<isize>, second: &Vector3<isize>, tolerance: isize) -> bool {
+    first
+        .iter()
+        .zip(second)
+        .all(|(a, b)| a.abs_diff(*b) <= tolerance.unsigned_abs())
+}
+
+/// beacons of `beacons` that land near a stable beacon, as (beacon, stable beacon) pairs. the
+/// matching is one-to-one, closest pairs first, so a cluster of noisy beacons cannot all claim
+/// the same stable beacon.
+fn find_inliers(
+    beacons: &[Vector3<isize>],
+    stable_beacons: &[Vector3<isize>],
+    tolerance: isize,
+) -> Vec<(Vector3<isize>, Vector3<isize>)> {
+    let candidates = beacons
+        .iter()
+        .enumerate()
+        .flat_map(|(i, beacon)| {
+            stable_beacons
+                .iter()
+                .enumerate()
+                .filter(move |(_, stable)| within(beacon, stable, tolerance))
+                .map(move |(j, stable)| ((*stable - beacon).abs().sum(), i, j))
+        })
+        .sorted_unstable();
+
+    let mut used_beacons = vec![false; beacons.len()];
+    let mut used_stable = vec![false; stable_beacons.len()];
+    candidates
+        .filter_map(|(_, i, j)| {
+            if used_beacons[i] || used_stable[j] {
+                return None;
+            }
+            used_beacons[i] = true;
+            used_stable[j] = true;
+            Some((beacons[i], stable_beacons[j]))
+        })
+        .collect()
+}
+
+/// ransac-style search: every beacon correspondence under every orientation proposes a
+/// translation, the proposal with the most inliers wins and is then refined on those inliers.
+/// a proposal carries the noise of the correspondence it came from, so the search allows twice
+/// the tolerance and only the refined transform is held to the real one.
+fn find_approximate_orientation(
+    scanner: &Scanner,
+    target: &Scanner,
+    tolerance: Tolerance,
+) -> Option<(Scanner, f64)> {
+    let target_position = target.position?;
+    let stable_beacons = target
+        .beacons
+        .iter()
+        .map(|beacon| target_position + beacon.offset)
+        .collect::<Vec<_>>();
+    let required =
+        tolerance.min_inlier_ratio * scanner.beacons.len().min(target.beacons.len()) as f64;
+    let search_tolerance = tolerance.position * 2;
+
+    let (orientation, position, inliers) = ORIENTATIONS
+        .iter()
+        .flat_map(|orientation| {
+            let rotated = scanner
+                .beacons
+                .iter()
+                .map(|beacon| orientation * beacon.offset)
+                .collect::<Vec<_>>();
+            let mut proposals = HashSet::new();
+
+            stable_beacons
+                .iter()
+                .cartesian_product(&rotated)
+                .filter_map(|(stable, beacon)| {
+                    let position = stable - beacon;
+                    if !proposals.insert(position) {
+                        return None;
+                    }
+
+                    let moved = rotated
+                        .iter()
+                        .map(|beacon| position + beacon)
+                        .collect::<Vec<_>>();
+                    let inliers = find_inliers(&moved, &stable_beacons, search_tolerance);
+                    Some((*orientation, position, inliers))
+                })
+                .collect::<Vec<_>>()
+        })
+        .max_by_key(|(_, _, inliers)| inliers.len())?;
+
+    if (inliers.len() as f64) < required || inliers.is_empty() {
+        return None;
+    }
+
+    // shift by the mean disagreement among the inliers
+    let count = inliers.len() as f64;
+    let correction = inliers
+        .iter()
+        .fold(Vector3::<f64>::zeros(), |sum, (beacon, stable)| {
+            sum + (stable - beacon).map(|v| v as f64)
+        })
+        / count;
+    let position = position + correction.map(|v| v.round() as isize);
+
+    let moved = scanner
+        .beacons
         .iter()
-        .map(|orient| reorient(scanner, orient))
-        .filter_map(|scanner| reposition(&scanner, target, threshold))
-        .next()
+        .map(|beacon| position + orientation * beacon.offset)
+        .collect::<Vec<_>>();
+    let inliers = find_inliers(&moved, &stable_beacons, tolerance.position);
+    if (inliers.len() as f64) < required || inliers.is_empty() {
+        return None;
+    }
+
+    let residual = (inliers
+        .iter()
+        .map(|(beacon, stable)| (stable - beacon).map(|v| v as f64).norm_squared())
+        .sum::<f64>()
+        / inliers.len() as f64)
+        .sqrt();
+
+    let mut scanner = reorient(scanner, &orientation);
+    scanner.position = Some(position);
+    Some((scanner, residual))
 }
 
 fn generate_beacon_graph(beacons: &[Beacon]) -> Array2<isize> {
@@ -263,14 +406,14 @@ fn parse_input(input: &str) -> Vec<Scanner> {
                 })
                 .collect::<Vec<_>>();
 
-            let beacon_graph = generate_beacon_graph(&beacons);
+            let fingerprints = generate_fingerprints(&generate_beacon_graph(&beacons));
 
             Scanner {
                 id: base_id,
                 position: Some(Vector3::new(0, 0, 0)),
                 rotation: None,
                 beacons,
-                beacon_graph,
+                fingerprints,
             }
         })
         .collect()
@@ -285,15 +428,6 @@ fn distance(first: &Beacon, second: &Beacon) -> isize {
         .sum()
 }
 
-fn taxicab_distance(first: &Beacon, second: &Beacon) -> Vec<isize> {
-    first
-        .offset
-        .iter()
-        .zip(&second.offset)
-        .map(|(a, b)| b - a)
-        .collect()
-}
-
 fn get_distances(scanner: &Scanner) -> HashMap<isize, usize> {
     scanner
         .beacons
@@ -313,13 +447,13 @@ fn init_graph(scanners: &[Scanner]) -> Array2<usize> {
 }
 
 fn build_graph(scanner_graph: &mut Array2<usize>, scanner_maps: &[HashMap<isize, usize>]) {
-    scanner_maps
-        .iter()
-        .enumerate()
-        .combinations(2)
-        .for_each(|combo| {
-            let (first_id, first_dists) = combo[0];
-            let (second_id, second_dists) = combo[1];
+    let overlaps = (0..scanner_maps.len())
+        .tuple_combinations()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(first_id, second_id)| {
+            let first_dists = &scanner_maps[first_id];
+            let second_dists = &scanner_maps[second_id];
 
             let overlap: usize = first_dists
                 .iter()
@@ -334,12 +468,19 @@ fn build_graph(scanner_graph: &mut Array2<usize>, scanner_maps: &[HashMap<isize,
                 })
                 .sum();
 
+            (first_id, second_id, overlap)
+        })
+        .collect::<Vec<_>>();
+
+    overlaps
+        .into_iter()
+        .for_each(|(first_id, second_id, overlap)| {
             scanner_graph[(first_id, second_id)] = overlap;
             scanner_graph[(second_id, first_id)] = overlap;
         });
 }
 
-fn count_beacons(scanners: &[Scanner]) -> usize {
+fn unique_beacons(scanners: &[&Scanner]) -> Vec<(isize, isize, isize)> {
     let mut all_beacons = scanners
         .iter()
         .flat_map(|scanner| {
@@ -355,7 +496,11 @@ fn count_beacons(scanners: &[Scanner]) -> usize {
 
     all_beacons.sort_unstable();
     all_beacons.dedup();
-    all_beacons.len()
+    all_beacons
+}
+
+fn count_beacons(scanners: &[&Scanner]) -> usize {
+    unique_beacons(scanners).len()
 }
 
 fn graph_to_edges(graph: &Array2<usize>, threshold: usize) -> Vec<(usize, usize, usize)> {
@@ -373,130 +518,294 @@ fn graph_to_edges(graph: &Array2<usize>, threshold: usize) -> Vec<(usize, usize,
         .collect()
 }
 
-fn reorient_scanners(
-    scanners: &[Scanner],
-    graph: &[(usize, usize, usize)],
-    threshold: usize,
-) -> Vec<Scanner> {
-    let mut scanners_oriented = scanners.iter().map(|_| None).collect::<Vec<_>>();
-    scanners_oriented[0] = Some(scanners[0].clone());
-
-    while scanners_oriented.iter().any(|scanner| scanner.is_none()) {
-        graph.iter().for_each(|(first, second, _)| {
-            println!(
-                "attempting to setup scanners {} and {} based on each other",
-                first, second
-            );
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Alignment {
+    /// every scanner, positioned relative to the anchor (first member) of its component
+    scanners: Vec<Scanner>,
+    /// groups of scanners that could be aligned to each other, anchor first
+    components: Vec<Vec<usize>>,
+    /// scanners that could not be matched against any other scanner
+    unmatched: Vec<usize>,
+    /// rms distance between matched beacons for every (stable, placed) scanner pair
+    residuals: Vec<(usize, usize, f64)>,
+}
 
-            let first_scanner = &scanners_oriented[*first];
-            let second_scanner = &scanners_oriented[*second];
-            match (first_scanner, second_scanner) {
-                (Some(_), Some(_)) => {
-                    println!("both are oriented, continuing");
-                }
-                /* find orientation for second */
-                (Some(first_scanner), None) => {
-                    println!("first has an entry");
-                    match find_right_orientation(&scanners[*second], first_scanner, threshold) {
-                        Some(scanner) => scanners_oriented[*second] = Some(scanner),
-                        None => panic!("could not find orientation for scanner {}", second),
-                    }
-                }
-                /* find orientation for first */
-                (None, Some(second_scanner)) => {
-                    println!("second has an entry");
-                    match find_right_orientation(&scanners[*first], second_scanner, threshold) {
-                        Some(scanner) => scanners_oriented[*first] = Some(scanner),
-                        None => panic!("could not find orientation for scanner {}", first),
-                    }
-                }
-                /* can't do anything until we get first or second oriented */
-                (None, None) => {
-                    println!("neither are currently oriented, skipping");
-                } // panic!("attempting to match two unmatched scanners {} and {}", first, second),
-            }
-            // let beacons = first_scanner.beacons.iter().map(|beacon| {
-            //     first_scanner
-            //         .position
-            //         .map(|position| position + beacon.offset)
-            //         .map(|vec| (vec[0], vec[1], vec[2]))
-            //         .unwrap_or_default()
-            // }).collect::<Vec<_>>();
-            // println!("looking from {} {:?}", first_scanner.id, beacons);
-            // match find_right_orientation(&scanners[*second], &scanners_oriented[*first], threshold) {
-            //     Some(scanner) => scanners_oriented[*second] = Some(scanner),
-            //     None => panic!("could not find orientation for scanner {}", second),
-            // }
-        });
+impl Alignment {
+    /// scanners sharing a frame of reference with scanner 0
+    pub fn placed(&self) -> &[usize] {
+        self.components
+            .iter()
+            .find(|component| component.contains(&0))
+            .map(|component| component.as_slice())
+            .unwrap_or_default()
     }
 
-    scanners_oriented
-        .iter()
-        .map(|scanner| scanner.as_ref().unwrap())
-        .cloned()
-        .collect()
-}
+    pub fn components(&self) -> &[Vec<usize>] {
+        &self.components
+    }
 
-fn solve_p1(scanners: &[Scanner], overlap_threshold: usize) -> usize {
-    let mut scanner_graph = init_graph(scanners);
+    pub fn unmatched(&self) -> &[usize] {
+        &self.unmatched
+    }
 
-    let scanner_maps = scanners
-        .iter()
-        .map(get_distances)
-        .collect::<Vec<_>>();
+    pub fn residuals(&self) -> &[(usize, usize, f64)] {
+        &self.residuals
+    }
 
-    build_graph(&mut scanner_graph, &scanner_maps);
+    fn component_scanners(&self) -> impl Iterator<Item = Vec<&Scanner>> {
+        self.components
+            .iter()
+            .map(|component| component.iter().map(|id| &self.scanners[*id]).collect())
+    }
 
-    let edges = graph_to_edges(&scanner_graph, overlap_threshold);
+    /// every unique beacon, tagged with the component whose frame it is expressed in
+    pub fn beacons(&self) -> Vec<(usize, (isize, isize, isize))> {
+        self.component_scanners()
+            .enumerate()
+            .flat_map(|(component, scanners)| {
+                unique_beacons(&scanners)
+                    .into_iter()
+                    .map(move |beacon| (component, beacon))
+            })
+            .collect()
+    }
+
+    /// every scanner with its component, position and rotation
+    pub fn scanners(&self) -> Vec<(usize, usize, Vector3<isize>, Matrix3<isize>)> {
+        let mut scanners = self
+            .components
+            .iter()
+            .enumerate()
+            .flat_map(|(component, ids)| {
+                ids.iter().map(move |id| {
+                    let scanner = &self.scanners[*id];
+                    (
+                        *id,
+                        component,
+                        scanner.position.unwrap_or_default(),
+                        scanner.rotation.unwrap_or_else(Matrix3::identity),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
 
-    println!("{:?}", edges);
+        scanners.sort_unstable_by_key(|scanner| scanner.0);
+        scanners
+    }
 
-    let mut tree = kruskal_indices(scanners.len(), &edges)
-        .map(|(first, second, weight)| (first.min(second), first.max(second), weight))
-        .collect::<Vec<_>>();
+    /// one row per scanner then one row per beacon; beacons leave the rotation columns empty
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "kind,id,component,x,y,z,r11,r12,r13,r21,r22,r23,r31,r32,r33"
+        )?;
+
+        for (id, component, position, rotation) in self.scanners() {
+            // nalgebra iterates column-major, we want the rows
+            let rotation = rotation.transpose().iter().join(",");
+            writeln!(
+                out,
+                "scanner,{},{},{},{},{},{}",
+                id, component, position.x, position.y, position.z, rotation
+            )?;
+        }
 
-    // tree.sort_unstable();
+        for (id, (component, (x, y, z))) in self.beacons().into_iter().enumerate() {
+            writeln!(
+                out,
+                "beacon,{},{},{},{},{},,,,,,,,,",
+                id, component, x, y, z
+            )?;
+        }
 
-    println!("{:?}", tree);
+        Ok(())
+    }
 
-    let scanners = reorient_scanners(scanners, &tree, overlap_threshold);
+    /// ascii point cloud with scanners in red and beacons in white. ply has no place for the
+    /// scanner rotations, so they are written as header comments.
+    pub fn write_ply<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let scanners = self.scanners();
+        let beacons = self.beacons();
+
+        writeln!(out, "ply")?;
+        writeln!(out, "format ascii 1.0")?;
+        for (id, component, _, rotation) in &scanners {
+            writeln!(
+                out,
+                "comment scanner {} component {} rotation {}",
+                id,
+                component,
+                rotation.transpose().iter().join(" ")
+            )?;
+        }
+        writeln!(out, "element vertex {}", scanners.len() + beacons.len())?;
+        for property in [
+            "int x",
+            "int y",
+            "int z",
+            "uchar red",
+            "uchar green",
+            "uchar blue",
+        ] {
+            writeln!(out, "property {}", property)?;
+        }
+        writeln!(out, "end_header")?;
 
-    // println!("scanners: {:?}", scanners);
+        for (_, _, position, _) in &scanners {
+            writeln!(out, "{} {} {} 255 0 0", position.x, position.y, position.z)?;
+        }
+        for (_, (x, y, z)) in &beacons {
+            writeln!(out, "{} {} {} 255 255 255", x, y, z)?;
+        }
 
-    count_beacons(&scanners)
+        Ok(())
+    }
 }
 
-fn solve_p2(scanners: &[Scanner], overlap_threshold: usize) -> usize {
+fn reorient_scanners<F>(
+    scanners: &[Scanner],
+    graph: &[(usize, usize, usize)],
+    matcher: F,
+) -> Alignment
+where
+    F: Fn(&Scanner, &Scanner) -> Option<(Scanner, f64)> + Sync,
+{
+    let mut scanners_oriented: Vec<Option<Scanner>> = scanners.iter().map(|_| None).collect();
+    let mut failed = HashSet::new();
+    let mut residuals = Vec::new();
+    let mut components = Vec::new();
+    let mut unmatched = Vec::new();
+
+    // anchor a new component on the lowest unplaced scanner until every scanner has a home
+    while let Some(anchor) = scanners_oriented
+        .iter()
+        .position(|scanner| scanner.is_none())
+    {
+        let mut anchored = scanners[anchor].clone();
+        anchored.position = Some(Vector3::zeros());
+        anchored.rotation = Some(Matrix3::identity());
+        scanners_oriented[anchor] = Some(anchored);
+
+        let mut component = vec![anchor];
+        let mut progress = true;
+
+        while progress {
+            progress = false;
+
+            let candidates = graph
+                .iter()
+                .filter_map(|(first, second, _)| {
+                    match (&scanners_oriented[*first], &scanners_oriented[*second]) {
+                        (Some(_), None) => Some((*first, *second)),
+                        (None, Some(_)) => Some((*second, *first)),
+                        /* either both are done or we can't do anything until one is oriented */
+                        _ => None,
+                    }
+                })
+                .filter(|pair| component.contains(&pair.0) && !failed.contains(pair))
+                .unique()
+                .collect::<Vec<_>>();
+
+            // verify every frontier pair at once, then place each target from the first hit
+            let results = candidates
+                .par_iter()
+                .map(|(placed, target)| {
+                    let stable = scanners_oriented[*placed].as_ref().unwrap();
+                    matcher(&scanners[*target], stable)
+                })
+                .collect::<Vec<_>>();
+
+            candidates.into_iter().zip(results).for_each(
+                |((placed, target), result)| match result {
+                    Some((scanner, residual)) if scanners_oriented[target].is_none() => {
+                        scanners_oriented[target] = Some(scanner);
+                        residuals.push((placed, target, residual));
+                        component.push(target);
+                        progress = true;
+                    }
+                    Some(_) => {}
+                    None => {
+                        failed.insert((placed, target));
+                    }
+                },
+            );
+        }
+
+        if component.len() == 1 {
+            unmatched.push(anchor);
+        }
+        components.push(component);
+    }
+
+    Alignment {
+        scanners: scanners_oriented.into_iter().map(Option::unwrap).collect(),
+        components,
+        unmatched,
+        residuals,
+    }
+}
+
+pub fn align(scanners: &[Scanner], overlap_threshold: usize) -> Alignment {
     let mut scanner_graph = init_graph(scanners);
 
-    let scanner_maps = scanners
-        .iter()
-        .map(get_distances)
-        .collect::<Vec<_>>();
+    let scanner_maps = scanners.iter().map(get_distances).collect::<Vec<_>>();
 
     build_graph(&mut scanner_graph, &scanner_maps);
 
     let edges = graph_to_edges(&scanner_graph, overlap_threshold);
 
-    println!("{:?}", edges);
-
-    let mut tree = kruskal_indices(scanners.len(), &edges)
+    // try the spanning tree first, falling back to the remaining edges if a tree edge turns
+    // out to be a false positive
+    let tree = kruskal_indices(scanners.len(), &edges)
         .map(|(first, second, weight)| (first.min(second), first.max(second), weight))
+        .chain(edges.iter().copied())
         .collect::<Vec<_>>();
 
-    // tree.sort_unstable();
+    reorient_scanners(scanners, &tree, |scanner, stable| {
+        find_right_orientation(scanner, stable, overlap_threshold).map(|scanner| (scanner, 0.0))
+    })
+}
 
-    println!("{:?}", tree);
+/// aligns noisy scanners. there is no exact overlap graph to go on, so every pair is a candidate.
+pub fn align_approximate(scanners: &[Scanner], tolerance: Tolerance) -> Alignment {
+    let graph = (0..scanners.len())
+        .tuple_combinations()
+        .map(|(first, second)| (first, second, 0))
+        .collect::<Vec<_>>();
 
-    let scanners = reorient_scanners(scanners, &tree, overlap_threshold);
+    reorient_scanners(scanners, &graph, |scanner, stable| {
+        find_approximate_orientation(scanner, stable, tolerance)
+    })
+}
 
-    // println!("scanners: {:?}", scanners);
+fn solve_p1(scanners: &[Scanner], overlap_threshold: usize) -> usize {
+    let alignment = align(scanners, overlap_threshold);
 
-    scanners.iter().tuple_combinations().map(|(first, second)| {
-        let p1 = first.position.unwrap();
-        let p2 = second.position.unwrap();
-        p1.iter().zip(&p2).map(|(a, b)|a.abs_diff(*b)).sum()
-    }).max().unwrap()
+    // beacons in separate components can't be identified with each other, so count them apart
+    alignment
+        .component_scanners()
+        .map(|component| count_beacons(&component))
+        .sum()
+}
+
+fn solve_p2(scanners: &[Scanner], overlap_threshold: usize) -> usize {
+    let alignment = align(scanners, overlap_threshold);
+
+    alignment
+        .component_scanners()
+        .flat_map(|component| {
+            component
+                .into_iter()
+                .tuple_combinations()
+                .map(|(first, second)| {
+                    let p1 = first.position.unwrap();
+                    let p2 = second.position.unwrap();
+                    p1.iter().zip(&p2).map(|(a, b)| a.abs_diff(*b)).sum()
+                })
+                .collect::<Vec<_>>()
+        })
+        .max()
+        .unwrap_or_default()
 }
 
 #[aoc_generator(day19)]
@@ -516,6 +825,11 @@ pub fn wrapper_p2(input: &[Scanner]) -> usize {
 
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
+    use nalgebra::Vector3;
+
+    const LARGE_INPUT: &str = "--- scanner 0 ---\n404,-588,-901\n528,-643,409\n-838,591,734\n390,-675,-793\n-537,-823,-458\n-485,-357,347\n-345,-311,381\n-661,-816,-575\n-876,649,763\n-618,-824,-621\n553,345,-567\n474,580,667\n-447,-329,318\n-584,868,-557\n544,-627,-890\n564,392,-477\n455,729,728\n-892,524,684\n-689,845,-530\n423,-701,434\n7,-33,-71\n630,319,-379\n443,580,662\n-789,900,-551\n459,-707,401\n\n--- scanner 1 ---\n686,422,578\n605,423,415\n515,917,-361\n-336,658,858\n95,138,22\n-476,619,847\n-340,-569,-846\n567,-361,727\n-460,603,-452\n669,-402,600\n729,430,532\n-500,-761,534\n-322,571,750\n-466,-666,-811\n-429,-592,574\n-355,545,-477\n703,-491,-529\n-328,-685,520\n413,935,-424\n-391,539,-444\n586,-435,557\n-364,-763,-893\n807,-499,-711\n755,-354,-619\n553,889,-390\n\n--- scanner 2 ---\n649,640,665\n682,-795,504\n-784,533,-524\n-644,584,-595\n-588,-843,648\n-30,6,44\n-674,560,763\n500,723,-460\n609,671,-379\n-555,-800,653\n-675,-892,-343\n697,-426,-610\n578,704,681\n493,664,-388\n-671,-858,530\n-667,343,800\n571,-461,-707\n-138,-166,112\n-889,563,-600\n646,-828,498\n640,759,510\n-630,509,768\n-681,-892,-333\n673,-379,-804\n-742,-814,-386\n577,-820,562\n\n--- scanner 3 ---\n-589,542,597\n605,-692,669\n-500,565,-823\n-660,373,557\n-458,-679,-417\n-488,449,543\n-626,468,-788\n338,-750,-386\n528,-832,-391\n562,-778,733\n-938,-730,414\n543,643,-506\n-524,371,-870\n407,773,750\n-104,29,83\n378,-903,-323\n-778,-728,485\n426,699,580\n-438,-605,-362\n-469,-447,-387\n509,732,623\n647,635,-688\n-868,-804,481\n614,-800,639\n595,780,-596\n\n--- scanner 4 ---\n727,592,562\n-293,-554,779\n441,611,-461\n-714,465,-776\n-743,427,-804\n-660,-479,-426\n832,-632,460\n927,-485,-438\n408,393,-506\n466,436,-512\n110,16,151\n-258,-428,682\n-393,719,612\n-211,-452,876\n808,-476,-593\n-575,615,604\n-485,667,467\n-680,325,-822\n-627,-443,-432\n872,-547,-609\n833,512,582\n807,604,487\n839,-516,451\n891,-625,532\n-652,-548,-490\n30,-46,-14";
+
     #[test]
     fn two_scanners() {
         let input =
@@ -539,10 +853,107 @@ mod tests {
 
     #[test]
     fn large_test() {
-        let input = "--- scanner 0 ---\n404,-588,-901\n528,-643,409\n-838,591,734\n390,-675,-793\n-537,-823,-458\n-485,-357,347\n-345,-311,381\n-661,-816,-575\n-876,649,763\n-618,-824,-621\n553,345,-567\n474,580,667\n-447,-329,318\n-584,868,-557\n544,-627,-890\n564,392,-477\n455,729,728\n-892,524,684\n-689,845,-530\n423,-701,434\n7,-33,-71\n630,319,-379\n443,580,662\n-789,900,-551\n459,-707,401\n\n--- scanner 1 ---\n686,422,578\n605,423,415\n515,917,-361\n-336,658,858\n95,138,22\n-476,619,847\n-340,-569,-846\n567,-361,727\n-460,603,-452\n669,-402,600\n729,430,532\n-500,-761,534\n-322,571,750\n-466,-666,-811\n-429,-592,574\n-355,545,-477\n703,-491,-529\n-328,-685,520\n413,935,-424\n-391,539,-444\n586,-435,557\n-364,-763,-893\n807,-499,-711\n755,-354,-619\n553,889,-390\n\n--- scanner 2 ---\n649,640,665\n682,-795,504\n-784,533,-524\n-644,584,-595\n-588,-843,648\n-30,6,44\n-674,560,763\n500,723,-460\n609,671,-379\n-555,-800,653\n-675,-892,-343\n697,-426,-610\n578,704,681\n493,664,-388\n-671,-858,530\n-667,343,800\n571,-461,-707\n-138,-166,112\n-889,563,-600\n646,-828,498\n640,759,510\n-630,509,768\n-681,-892,-333\n673,-379,-804\n-742,-814,-386\n577,-820,562\n\n--- scanner 3 ---\n-589,542,597\n605,-692,669\n-500,565,-823\n-660,373,557\n-458,-679,-417\n-488,449,543\n-626,468,-788\n338,-750,-386\n528,-832,-391\n562,-778,733\n-938,-730,414\n543,643,-506\n-524,371,-870\n407,773,750\n-104,29,83\n378,-903,-323\n-778,-728,485\n426,699,580\n-438,-605,-362\n-469,-447,-387\n509,732,623\n647,635,-688\n-868,-804,481\n614,-800,639\n595,780,-596\n\n--- scanner 4 ---\n727,592,562\n-293,-554,779\n441,611,-461\n-714,465,-776\n-743,427,-804\n-660,-479,-426\n832,-632,460\n927,-485,-438\n408,393,-506\n466,436,-512\n110,16,151\n-258,-428,682\n-393,719,612\n-211,-452,876\n808,-476,-593\n-575,615,604\n-485,667,467\n-680,325,-822\n-627,-443,-432\n872,-547,-609\n833,512,582\n807,604,487\n839,-516,451\n891,-625,532\n-652,-548,-490\n30,-46,-14";
-        let parsed_input = super::input_generator(input);
+        let parsed_input = super::input_generator(LARGE_INPUT);
 
         assert_eq!(79, super::solve_p1(&parsed_input, 12));
         assert_eq!(3621, super::solve_p2(&parsed_input, 12));
     }
+
+    #[test]
+    fn fingerprint_orientation() {
+        let parsed_input = super::input_generator(LARGE_INPUT);
+
+        let scanner =
+            super::find_right_orientation(&parsed_input[1], &parsed_input[0], 12).unwrap();
+        assert_eq!(Some(Vector3::new(68, -1246, -43)), scanner.position);
+
+        let scanner = super::find_right_orientation(&parsed_input[4], &scanner, 12).unwrap();
+        assert_eq!(Some(Vector3::new(-20, -1133, 1061)), scanner.position);
+    }
+
+    #[test]
+    fn export_map() {
+        let input =
+            "--- scanner 0 ---\n0,2,0\n4,1,0\n3,3,0\n\n--- scanner 1 ---\n-1,-1,0\n-5,0,0\n-2,1,0";
+        let parsed_input = super::input_generator(input);
+        let alignment = super::align(&parsed_input, 3);
+
+        let mut csv = Vec::new();
+        alignment.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            "kind,id,component,x,y,z,r11,r12,r13,r21,r22,r23,r31,r32,r33\n\
+             scanner,0,0,0,0,0,1,0,0,0,1,0,0,0,1\n\
+             scanner,1,0,5,2,0,1,0,0,0,1,0,0,0,1\n\
+             beacon,0,0,0,2,0,,,,,,,,,\n\
+             beacon,1,0,3,3,0,,,,,,,,,\n\
+             beacon,2,0,4,1,0,,,,,,,,,\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let mut ply = Vec::new();
+        alignment.write_ply(&mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains("element vertex 5\n"));
+        assert!(ply.ends_with("end_header\n0 0 0 255 0 0\n5 2 0 255 0 0\n0 2 0 255 255 255\n3 3 0 255 255 255\n4 1 0 255 255 255\n"));
+    }
+
+    #[test]
+    fn noisy_alignment() {
+        // jitter every coordinate by up to one unit
+        let noisy = LARGE_INPUT
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                if line.starts_with("---") || line.is_empty() {
+                    line.to_string()
+                } else {
+                    line.split(',')
+                        .enumerate()
+                        .map(|(j, v)| {
+                            (v.parse::<isize>().unwrap() + ((i + j) % 3) as isize - 1).to_string()
+                        })
+                        .join(",")
+                }
+            })
+            .join("\n");
+        let parsed_input = super::input_generator(&noisy);
+
+        let tolerance = super::Tolerance {
+            position: 2,
+            min_inlier_ratio: 0.4,
+        };
+        let alignment = super::align_approximate(&parsed_input, tolerance);
+
+        assert_eq!(&[vec![0, 1, 3, 4, 2]], alignment.components());
+        assert_eq!(4, alignment.residuals().len());
+        assert!(alignment
+            .residuals()
+            .iter()
+            .all(|(_, _, residual)| *residual > 0.0 && *residual <= 2.0 * 3f64.sqrt()));
+
+        let (_, _, position, _) = alignment.scanners()[1];
+        assert!(super::within(&position, &Vector3::new(68, -1246, -43), 2));
+
+        // a cluster of moved beacons can only claim each stable beacon once
+        let cluster = [
+            Vector3::new(0, 0, 1),
+            Vector3::new(0, 1, 0),
+            Vector3::new(1, 0, 0),
+        ];
+        let stable = [Vector3::new(0, 0, 0), Vector3::new(9, 9, 9)];
+        assert_eq!(1, super::find_inliers(&cluster, &stable, 2).len());
+    }
+
+    #[test]
+    fn disconnected_scanners() {
+        let input = "--- scanner 0 ---\n0,2,0\n4,1,0\n3,3,0\n\n--- scanner 1 ---\n-1,-1,0\n-5,0,0\n-2,1,0\n\n--- scanner 2 ---\n100,0,0\n0,100,0\n0,0,100";
+        let parsed_input = super::input_generator(input);
+
+        let alignment = super::align(&parsed_input, 3);
+
+        assert_eq!(&[0, 1], alignment.placed());
+        assert_eq!(&[vec![0, 1], vec![2]], alignment.components());
+        assert_eq!(&[2], alignment.unmatched());
+        assert_eq!(6, super::solve_p1(&parsed_input, 3));
+    }
 }
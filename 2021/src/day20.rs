@@ -1,152 +1,485 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
 use aoc_runner_derive::{aoc, aoc_generator};
-use ndarray::Array2;
+use itertools::Itertools;
+use ndarray::{s, Array2};
 
-fn parse_input(input: &str) -> ([u8; 512], Array2<u8>) {
-    let mut algorithm = [0; 512];
-    let (alg, image) = input.split_once("\n\n").unwrap();
-    alg.bytes()
-        .enumerate()
-        .filter(|(_, chr)| *chr == b'#')
-        .for_each(|(ind, _)| {
-            algorithm[ind] = 1;
-        });
+/// a window of pixels on an infinite plane. every pixel outside the window is `background`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// plane coordinate (row, col) of the top left pixel of the window
+    origin: (isize, isize),
+    pixels: Array2<u8>,
+    background: u8,
+}
 
-    let image_rows = image.lines().count();
-    let image_cols = image.lines().next().unwrap().len();
+impl Image {
+    pub fn new(origin: (isize, isize), pixels: Array2<u8>, background: u8) -> Self {
+        Image {
+            origin,
+            pixels,
+            background,
+        }
+    }
 
-    let image_vec = image
-        .bytes()
-        .filter(|chr| *chr != b'\n')
-        .map(|chr| if chr == b'#' { 1 } else { 0 })
-        .collect();
+    /// the (top left, bottom right) plane coordinates of the window, inclusive
+    pub fn bounds(&self) -> ((isize, isize), (isize, isize)) {
+        let (rows, cols) = self.pixels.dim();
+        (
+            self.origin,
+            (
+                self.origin.0 + rows as isize - 1,
+                self.origin.1 + cols as isize - 1,
+            ),
+        )
+    }
+
+    pub fn get(&self, row: isize, col: isize) -> u8 {
+        let (row, col) = (row - self.origin.0, col - self.origin.1);
+        if row < 0 || col < 0 {
+            return self.background;
+        }
+
+        *self
+            .pixels
+            .get((row as usize, col as usize))
+            .unwrap_or(&self.background)
+    }
+
+    pub fn background(&self) -> u8 {
+        self.background
+    }
 
-    let image = Array2::<u8>::from_shape_vec((image_cols, image_rows), image_vec).unwrap();
+    pub fn pixels(&self) -> &Array2<u8> {
+        &self.pixels
+    }
+
+    /// lit pixels inside the window. if the background is lit there are infinitely many more.
+    pub fn lit(&self) -> usize {
+        self.pixels.iter().map(|i| *i as usize).sum()
+    }
+}
+
+impl Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.pixels.rows() {
+            for cell in row.iter() {
+                if *cell == 1 {
+                    write!(f, "#")?;
+                } else {
+                    write!(f, ".")?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
 
-    (algorithm, image)
+/// an enhancement algorithm over a square, odd sized kernel. the table holds the output for
+/// every kernel, read row by row with the top left pixel as the most significant bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enhancer {
+    kernel: usize,
+    table: Vec<u8>,
 }
 
-fn print_image(image: &Array2<u8>) {
-    for row in image.rows() {
-        for cell in row.iter() {
-            if *cell == 1 {
-                print!("#");
+impl Enhancer {
+    /// the kernel size is inferred from the table, which must have 2^(k*k) entries for odd k
+    pub fn new(table: Vec<u8>) -> Self {
+        let bits = table.len().trailing_zeros() as usize;
+        let kernel = (1..).find(|k| k * k >= bits).unwrap();
+
+        assert!(
+            table.len().is_power_of_two() && kernel * kernel == bits && kernel % 2 == 1,
+            "table of {} entries does not fit an odd square kernel",
+            table.len()
+        );
+
+        Enhancer { kernel, table }
+    }
+
+    pub fn kernel(&self) -> usize {
+        self.kernel
+    }
+
+    pub fn enhance(&self, image: &Image) -> Image {
+        self.run(image, 1)
+    }
+
+    /// enhances `steps` times. the canvas for the final image is allocated once up front and
+    /// the steps alternate between two buffers, each growing the window by the kernel radius.
+    pub fn run(&self, image: &Image, steps: usize) -> Image {
+        let radius = self.kernel / 2;
+        let grow = radius * steps;
+        let (rows, cols) = image.pixels.dim();
+        let shape = (rows + 2 * grow, cols + 2 * grow);
+
+        let mut current = Array2::zeros(shape);
+        current
+            .slice_mut(s![grow..grow + rows, grow..grow + cols])
+            .assign(&image.pixels);
+        let mut next = Array2::zeros(shape);
+
+        // (top, left, bottom, right) of the window in the canvas, exclusive
+        let mut window = (grow, grow, grow + rows, grow + cols);
+        let mut background = image.background;
+
+        for _step in 0..steps {
+            let (top, left, bottom, right) = window;
+            let read = |row: usize, col: usize| {
+                if row < top || row >= bottom || col < left || col >= right {
+                    background
+                } else {
+                    current[(row, col)]
+                }
+            };
+
+            let new_window = (top - radius, left - radius, bottom + radius, right + radius);
+            for row in new_window.0..new_window.2 {
+                for col in new_window.1..new_window.3 {
+                    // reads off the top or left of the canvas wrap around to huge indices,
+                    // which land outside the window and read as background
+                    let index = (0..self.kernel)
+                        .flat_map(|dr| (0..self.kernel).map(move |dc| (dr, dc)))
+                        .fold(0, |agg, (dr, dc)| {
+                            let (row, col) = (
+                                (row + dr).wrapping_sub(radius),
+                                (col + dc).wrapping_sub(radius),
+                            );
+                            agg << 1 | read(row, col) as usize
+                        });
+                    next[(row, col)] = self.table[index];
+                }
+            }
+
+            background = self.table[if background == 1 {
+                self.table.len() - 1
             } else {
-                print!(".");
+                0
+            }];
+            std::mem::swap(&mut current, &mut next);
+            window = new_window;
+        }
+
+        let (top, left, bottom, right) = window;
+        Image {
+            origin: (
+                image.origin.0 - grow as isize,
+                image.origin.1 - grow as isize,
+            ),
+            pixels: current.slice(s![top..bottom, left..right]).to_owned(),
+            background,
+        }
+    }
+}
+
+impl Enhancer {
+    /// builds the 3x3 table for a life-like rulestring such as `B3/S23` or `B36/S23`. the counts
+    /// after `B` bring a dead cell to life and the counts after `S` keep a live cell alive.
+    pub fn from_rulestring(rule: &str) -> Option<Self> {
+        let mut birth = [false; 9];
+        let mut survival = [false; 9];
+
+        for part in rule.trim().split('/') {
+            let mut chars = part.chars();
+            let counts = match chars.next()?.to_ascii_uppercase() {
+                'B' => &mut birth,
+                'S' => &mut survival,
+                _ => return None,
+            };
+
+            for chr in chars {
+                let count = chr.to_digit(10).filter(|count| *count < 9)?;
+                counts[count as usize] = true;
             }
         }
-        println!();
+
+        // the centre of a 3x3 kernel is bit 4
+        let table = (0..512usize)
+            .map(|kernel| {
+                let neighbours = (kernel & !(1 << 4)).count_ones() as usize;
+                let alive = kernel & (1 << 4) != 0;
+                let next = if alive {
+                    survival[neighbours]
+                } else {
+                    birth[neighbours]
+                };
+                next as u8
+            })
+            .collect();
+
+        Some(Enhancer::new(table))
     }
 }
 
-fn around(row: usize, col: usize) -> Vec<(usize, usize)> {
-    vec![
-        (row - 1, col - 1),
-        (row - 1, col),
-        (row - 1, col + 1),
-        (row, col - 1),
-        (row, col),
-        (row, col + 1),
-        (row + 1, col - 1),
-        (row + 1, col),
-        (row + 1, col + 1),
-    ]
+/// reads a run length encoded pattern, along with the rule from its header if it has one
+pub fn parse_rle(input: &str) -> (Image, Option<Enhancer>) {
+    let mut lines = input.lines().filter(|line| !line.starts_with('#'));
+    let header = lines.next().unwrap();
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut rule = None;
+    header.split(',').for_each(|field| {
+        let (key, value) = field.split_once('=').unwrap();
+        match key.trim() {
+            "x" => width = value.trim().parse().unwrap(),
+            "y" => height = value.trim().parse().unwrap(),
+            "rule" => rule = Enhancer::from_rulestring(value),
+            _ => {}
+        }
+    });
+
+    let mut pixels = Array2::zeros((height, width));
+    let (mut row, mut col) = (0, 0);
+    let mut run = 0;
+
+    for chr in lines.flat_map(|line| line.trim().chars()) {
+        let count = run.max(1);
+        match chr {
+            '0'..='9' => {
+                run = run * 10 + chr.to_digit(10).unwrap() as usize;
+                continue;
+            }
+            'b' | '.' => col += count,
+            '$' => {
+                row += count;
+                col = 0;
+            }
+            '!' => break,
+            _ => {
+                pixels.slice_mut(s![row..row + 1, col..col + count]).fill(1);
+                col += count;
+            }
+        }
+        run = 0;
+    }
+
+    (Image::new((0, 0), pixels, 0), rule)
 }
 
-fn kernel(image: &Array2<u8>, row: usize, col: usize) -> usize {
-    around(row, col)
-        .iter()
-        .fold(0, |agg, coord| agg << 1 | image[*coord] as usize)
+/// reads a plaintext (`.cells`) pattern, `O` for live cells and `.` for dead ones
+pub fn parse_plaintext(input: &str) -> Image {
+    let rows = input
+        .lines()
+        .filter(|line| !line.starts_with('!'))
+        .collect::<Vec<_>>();
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or_default();
+
+    let pixels = Array2::from_shape_fn((rows.len(), width), |(row, col)| {
+        (rows[row].as_bytes().get(col) == Some(&b'O')) as u8
+    });
+
+    Image::new((0, 0), pixels, 0)
 }
 
-fn expand(image: &Array2<u8>, unseen_state: u8) -> Array2<u8> {
-    let image_shape = image.shape();
-    Array2::from_shape_fn((image_shape[0] + 4, image_shape[1] + 4), |(row, col)| {
-        if row < 2 || row > image_shape[0] + 1 || col < 2 || col > image_shape[1] + 1 {
-            unseen_state
-        } else if row > 1 && row < image_shape[0] + 2 && col > 1 && col < image_shape[1] + 2 {
-            image[(row - 2, col - 2)]
-        } else {
-            0
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// reads the next whitespace separated header token, skipping `#` comments
+fn pbm_token<'a>(input: &'a [u8], position: &mut usize) -> io::Result<&'a [u8]> {
+    loop {
+        match input.get(*position) {
+            Some(b'#') => {
+                while !matches!(input.get(*position), Some(b'\n') | None) {
+                    *position += 1;
+                }
+            }
+            Some(chr) if chr.is_ascii_whitespace() => *position += 1,
+            Some(_) => break,
+            None => return Err(invalid("truncated pbm header")),
         }
-    })
+    }
+
+    let start = *position;
+    while matches!(input.get(*position), Some(chr) if !chr.is_ascii_whitespace()) {
+        *position += 1;
+    }
+
+    Ok(&input[start..*position])
 }
 
-fn contract(image: &Array2<u8>) -> Array2<u8> {
-    let image_shape = image.shape();
-    Array2::from_shape_fn((image_shape[0] - 2, image_shape[1] - 2), |(row, col)| {
-        image[(row + 1, col + 1)]
-    })
+fn pbm_number(input: &[u8], position: &mut usize) -> io::Result<usize> {
+    std::str::from_utf8(pbm_token(input, position)?)
+        .ok()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| invalid("bad pbm dimension"))
 }
 
-fn enhance(algorithm: &[u8; 512], image: &Array2<u8>, unseen_state: u8) -> Array2<u8> {
-    let ref_image = expand(image, unseen_state);
-    let shape = ref_image.shape();
+impl Image {
+    /// reads a plain (P1) or raw (P4) netpbm bitmap. black pixels are lit.
+    pub fn read_pbm(input: &[u8]) -> io::Result<Image> {
+        let mut position = 0;
+        let magic = pbm_token(input, &mut position)?;
+        let cols = pbm_number(input, &mut position)?;
+        let rows = pbm_number(input, &mut position)?;
+
+        let pixels = match magic {
+            b"P1" => input[position..]
+                .iter()
+                .filter(|chr| !chr.is_ascii_whitespace())
+                .take(rows * cols)
+                .map(|chr| match chr {
+                    b'0' => Ok(0),
+                    b'1' => Ok(1),
+                    _ => Err(invalid("bad pixel in plain pbm")),
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+            b"P4" => {
+                // exactly one whitespace byte separates the header from the raster
+                let raster = input.get(position + 1..).unwrap_or_default();
+                let row_bytes = cols.div_ceil(8);
+                (0..rows * cols)
+                    .map(|i| {
+                        let (row, col) = (i / cols, i % cols);
+                        raster
+                            .get(row * row_bytes + col / 8)
+                            .map(|byte| byte >> (7 - col % 8) & 1)
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("truncated raw pbm"))?
+            }
+            _ => return Err(invalid("not a pbm bitmap")),
+        };
+
+        let pixels = Array2::from_shape_vec((rows, cols), pixels)
+            .map_err(|_| invalid("truncated plain pbm"))?;
+        Ok(Image::new((0, 0), pixels, 0))
+    }
+
+    /// writes the window as a netpbm bitmap, raw (P4) if `raw` and plain (P1) otherwise. lit
+    /// pixels are black. the background and the window's origin are not stored.
+    pub fn write_pbm<W: Write>(&self, out: &mut W, raw: bool) -> io::Result<()> {
+        let (rows, cols) = self.pixels.dim();
+
+        if raw {
+            write!(out, "P4\n{} {}\n", cols, rows)?;
+            for row in self.pixels.rows() {
+                let bytes = row
+                    .iter()
+                    .chunks(8)
+                    .into_iter()
+                    .map(|chunk| {
+                        chunk
+                            .enumerate()
+                            .fold(0u8, |byte, (bit, pixel)| byte | pixel << (7 - bit))
+                    })
+                    .collect::<Vec<_>>();
+                out.write_all(&bytes)?;
+            }
+        } else {
+            writeln!(out, "P1\n{} {}", cols, rows)?;
+            for row in self.pixels.rows() {
+                writeln!(out, "{}", row.iter().join(" "))?;
+            }
+        }
+
+        Ok(())
+    }
 
-    let mut new_image = Array2::from_shape_simple_fn((shape[0], shape[1]), || 0);
+    /// writes the window as a raw (P5) greymap with every pixel blown up to a `scale` x `scale`
+    /// square. lit pixels are black to match the bitmaps.
+    pub fn write_pgm<W: Write>(&self, out: &mut W, scale: usize) -> io::Result<()> {
+        let (rows, cols) = self.pixels.dim();
+        write!(out, "P5\n{} {}\n255\n", cols * scale, rows * scale)?;
 
-    // inside rows
-    for row in 1..(shape[0] - 1) {
-        // inside cols
-        for col in 1..(shape[1] - 1) {
-            new_image[(row, col)] = algorithm[kernel(&ref_image, row, col)];
+        for row in self.pixels.rows() {
+            let line = row
+                .iter()
+                .flat_map(|pixel| std::iter::repeat_n(if *pixel == 1 { 0 } else { 255 }, scale))
+                .collect::<Vec<u8>>();
+            for _ in 0..scale {
+                out.write_all(&line)?;
+            }
         }
+
+        Ok(())
     }
+}
 
-    // keep just inside rows, cols
-    contract(&new_image)
+fn parse_table(input: &str) -> Vec<u8> {
+    input
+        .bytes()
+        .filter(|chr| !chr.is_ascii_whitespace())
+        .map(|chr| if chr == b'#' { 1 } else { 0 })
+        .collect()
 }
 
-fn steps(algorithm: &[u8; 512], image: &Array2<u8>, steps: usize, debug: bool) -> Array2<u8> {
-    let mut infinite_state = 0;
-    let mut image = image.clone();
+fn parse_image(input: &str) -> Image {
+    let image_rows = input.lines().count();
+    let image_cols = input.lines().next().unwrap().len();
+
+    let image_vec = input
+        .bytes()
+        .filter(|chr| *chr != b'\n')
+        .map(|chr| if chr == b'#' { 1 } else { 0 })
+        .collect();
 
+    let pixels = Array2::<u8>::from_shape_vec((image_rows, image_cols), image_vec).unwrap();
+
+    Image::new((0, 0), pixels, 0)
+}
+
+fn parse_input(input: &str) -> (Enhancer, Image) {
+    let (alg, image) = input.split_once("\n\n").unwrap();
+
+    (Enhancer::new(parse_table(alg)), parse_image(image))
+}
+
+fn print_image(image: &Image) {
+    print!("{}", image);
+}
+
+fn steps(enhancer: &Enhancer, image: &Image, steps: usize, debug: bool) -> Image {
+    if !debug {
+        return enhancer.run(image, steps);
+    }
+
+    let mut image = image.clone();
     for _step in 0..steps {
-        // println!("step {}: infinite @ {}", _step, infinite_state);
-        image = enhance(algorithm, &image, infinite_state);
-        infinite_state = algorithm[(infinite_state..=infinite_state)
-            .cycle()
-            .take(9)
-            .fold(0usize, |agg, v| agg << 1 | v as usize)];
-
-        if debug {
-            print_image(&image);
-        }
+        image = enhancer.enhance(&image);
+        print_image(&image);
     }
 
     image
 }
 
-fn solve_p1((algorithm, image): &([u8; 512], Array2<u8>)) -> usize {
-    // println!("{:?}", image.shape());
+fn solve_p1((enhancer, image): &(Enhancer, Image)) -> usize {
     // print_image(image);
 
-    let image = steps(algorithm, image, 2, false);
+    let image = steps(enhancer, image, 2, false);
 
     // print_image(&image);
 
-    image.iter().map(|i| *i as usize).sum()
+    image.lit()
 }
 
-fn solve_p2((algorithm, image): &([u8; 512], Array2<u8>)) -> usize {
-    let image = steps(algorithm, image, 50, false);
+fn solve_p2((enhancer, image): &(Enhancer, Image)) -> usize {
+    let image = steps(enhancer, image, 50, false);
 
     // print_image(&image);
 
-    image.iter().map(|i| *i as usize).sum()
+    image.lit()
 }
 
 #[aoc_generator(day20)]
-pub fn input_generator(input: &str) -> ([u8; 512], Array2<u8>) {
+pub fn input_generator(input: &str) -> (Enhancer, Image) {
     parse_input(input)
 }
 
 #[aoc(day20 part1)]
-pub fn wrapper_p1(input: &([u8; 512], Array2<u8>)) -> usize {
+pub fn wrapper_p1(input: &(Enhancer, Image)) -> usize {
     solve_p1(input)
 }
 
 #[aoc(day20, part2)]
-pub fn wrapper_p2(input: &([u8; 512], Array2<u8>)) -> usize {
+pub fn wrapper_p2(input: &(Enhancer, Image)) -> usize {
     solve_p2(input)
 }
 
@@ -159,5 +492,97 @@ mod tests {
         let parsed_input = super::input_generator(input);
         assert_eq!(35, super::solve_p1(&parsed_input));
         assert_eq!(3351, super::solve_p2(&parsed_input));
+
+        let (enhancer, image) = &parsed_input;
+        let stepped = (0..4).fold(image.clone(), |image, _| enhancer.enhance(&image));
+        let run = enhancer.run(image, 4);
+        assert_eq!(stepped, run);
+        assert_eq!(((-4, -4), (8, 8)), run.bounds());
+    }
+
+    fn lit_cells(image: &super::Image) -> Vec<(isize, isize)> {
+        let ((top, left), (bottom, right)) = image.bounds();
+        (top..=bottom)
+            .flat_map(|row| (left..=right).map(move |col| (row, col)))
+            .filter(|(row, col)| image.get(*row, *col) == 1)
+            .collect()
+    }
+
+    #[test]
+    fn life() {
+        let life = super::Enhancer::from_rulestring("B3/S23").unwrap();
+        assert!(super::Enhancer::from_rulestring("B9/S23").is_none());
+        assert_eq!(
+            Some(&life),
+            super::Enhancer::from_rulestring("s23/b3").as_ref()
+        );
+
+        let blinker = super::parse_plaintext("!Name: Blinker\n.O\n.O\n.O");
+        assert_eq!(
+            vec![(1, 0), (1, 1), (1, 2)],
+            lit_cells(&life.enhance(&blinker))
+        );
+        assert_eq!(lit_cells(&blinker), lit_cells(&life.run(&blinker, 2)));
+
+        let (glider, rule) =
+            super::parse_rle("#N Glider\nx = 3, y = 3, rule = B3/S23\nbob$2bo$3o!");
+        assert_eq!(Some(life.clone()), rule);
+        let moved = lit_cells(&life.run(&glider, 4));
+        let expected = lit_cells(&glider)
+            .into_iter()
+            .map(|(row, col)| (row + 1, col + 1))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, moved);
+    }
+
+    #[test]
+    fn netpbm() {
+        let image = super::parse_image("#..#.\n#....\n##..#\n..#..\n..###");
+
+        let mut plain = Vec::new();
+        image.write_pbm(&mut plain, false).unwrap();
+        assert!(plain.starts_with(b"P1\n5 5\n1 0 0 1 0\n"));
+        assert_eq!(image, super::Image::read_pbm(&plain).unwrap());
+
+        let mut raw = Vec::new();
+        image.write_pbm(&mut raw, true).unwrap();
+        assert_eq!(b"P4\n5 5\n\x90\x80\xc8\x20\x38", raw.as_slice());
+        assert_eq!(image, super::Image::read_pbm(&raw).unwrap());
+
+        let commented = b"P1\n# a comment\n2 1\n01";
+        assert_eq!(
+            vec![0, 1],
+            super::Image::read_pbm(commented)
+                .unwrap()
+                .pixels()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert!(super::Image::read_pbm(b"P4\n9 1\n\xff").is_err());
+
+        let mut pgm = Vec::new();
+        image.write_pgm(&mut pgm, 3).unwrap();
+        assert!(pgm.starts_with(b"P5\n15 15\n255\n\0\0\0\xff\xff\xff"));
+        assert_eq!(b"P5\n15 15\n255\n".len() + 15 * 15, pgm.len());
+    }
+
+    #[test]
+    fn background() {
+        // a 1x1 kernel that inverts every pixel, including the infinite background
+        let enhancer = super::Enhancer::new(super::parse_table("#."));
+        let image = super::parse_image("#.\n..");
+        assert_eq!(1, enhancer.kernel());
+
+        let image = enhancer.enhance(&image);
+        assert_eq!(1, image.background());
+        assert_eq!(3, image.lit());
+        assert_eq!(0, image.get(0, 0));
+        assert_eq!(1, image.get(-100, 100));
+
+        let image = enhancer.run(&image, 3);
+        assert_eq!(0, image.background());
+        assert_eq!(1, image.lit());
+        assert_eq!(((0, 0), (1, 1)), image.bounds());
     }
 }
@@ -1,15 +1,62 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
-use lazy_static::lazy_static;
 
-fn position(score: usize) -> usize {
-    (score - 1) % 10 + 1
+/// everything that can vary between games of dirac dice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameRules {
+    /// spaces on the circular board, numbered from 1
+    pub board_size: usize,
+    /// faces on the dirac die, numbered from 1
+    pub die_faces: usize,
+    pub rolls_per_turn: usize,
+    pub players: usize,
+    pub target_score: usize,
+    /// faces on the deterministic die, which rolls 1, 2, 3, ... and wraps around
+    pub deterministic_die: usize,
 }
 
-fn triangle(rolls: usize) -> usize {
-    rolls * (rolls + 1) / 2
+impl Default for GameRules {
+    fn default() -> Self {
+        GameRules {
+            board_size: 10,
+            die_faces: 3,
+            rolls_per_turn: 3,
+            players: 2,
+            target_score: 1000,
+            deterministic_die: 100,
+        }
+    }
+}
+
+impl GameRules {
+    /// the rules of the puzzle's dirac dice game
+    pub fn dirac() -> Self {
+        GameRules {
+            target_score: 21,
+            ..Default::default()
+        }
+    }
+
+    fn position(&self, step: usize) -> usize {
+        (step - 1) % self.board_size + 1
+    }
+
+    /// how many universes roll each total in one turn
+    fn roll_distribution(&self) -> Vec<(usize, usize)> {
+        (0..self.rolls_per_turn)
+            .map(|_| 1..=self.die_faces)
+            .multi_cartesian_product()
+            .map(|rolls| rolls.iter().sum::<usize>())
+            .counts()
+            .into_iter()
+            .sorted()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -28,6 +75,11 @@ impl Player {
         }
     }
 
+    fn advance(&mut self, roll: usize, rules: &GameRules) {
+        self.position = rules.position(self.position + roll);
+        self.score += self.position;
+    }
+
     #[allow(unused)]
     fn print_state(&self, rolls: usize) {
         println!(
@@ -40,37 +92,32 @@ impl Player {
     }
 }
 
-fn score_players(first: &mut Player, second: &mut Player) -> usize {
-    let mut rolls = 0;
-
-    loop {
-        let base_rolls_score = triangle(rolls);
-        let mid_rolls_score = triangle(rolls + 3);
-        let high_rolls_score = triangle(rolls + 6);
+fn new_players(starts: &[usize], rules: &GameRules) -> Vec<Player> {
+    assert_eq!(rules.players, starts.len(), "one start per player");
 
-        let current_step = first.position + mid_rolls_score - base_rolls_score;
-        first.position = position(current_step);
-        first.score += position(current_step);
-
-        rolls += 3;
+    starts
+        .iter()
+        .enumerate()
+        .map(|(id, start)| Player::new(*start, id as u8 + 1))
+        .collect()
+}
 
-        // first.print_state(rolls);
-        if first.score >= 1000 {
-            break;
-        }
+/// plays with the deterministic die until someone wins, returning the number of rolls
+fn score_players(players: &mut [Player], rules: &GameRules) -> usize {
+    let mut die = (1..=rules.deterministic_die).cycle();
+    let mut rolls = 0;
 
-        let current_step = second.position + high_rolls_score - mid_rolls_score;
-        second.position = position(current_step);
-        second.score += position(current_step);
+    for turn in (0..players.len()).cycle() {
+        let roll = die.by_ref().take(rules.rolls_per_turn).sum::<usize>();
+        rolls += rules.rolls_per_turn;
 
-        rolls += 3;
+        let player = &mut players[turn];
+        player.advance(roll, rules);
 
-        // second.print_state(rolls);
-        if second.score >= 1000 {
+        // player.print_state(rolls);
+        if player.score >= rules.target_score {
             break;
         }
-
-        // std::thread::sleep(std::time::Duration::from_secs(1));
     }
 
     rolls
@@ -79,22 +126,20 @@ fn score_players(first: &mut Player, second: &mut Player) -> usize {
 fn parse_input(input: &str) -> Vec<usize> {
     input
         .lines()
-        .map(|line| line.bytes().last().unwrap() as usize - 48)
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
         .collect()
 }
 
-fn solve_p1(target: &[usize]) -> usize {
-    let mut first = Player::new(target[0], 1);
-    let mut second = Player::new(target[1], 2);
-    let rolls = score_players(&mut first, &mut second);
+/// the losing score times the number of rolls in a deterministic game
+pub fn play_deterministic(starts: &[usize], rules: &GameRules) -> usize {
+    let mut players = new_players(starts, rules);
+    let rolls = score_players(&mut players, rules);
 
-    println!("{:?} {:?} {}", first, second, rolls);
+    players.iter().map(|player| player.score).min().unwrap() * rolls
+}
 
-    if first.score < second.score {
-        first.score * rolls
-    } else {
-        second.score * rolls
-    }
+fn solve_p1(target: &[usize]) -> usize {
+    play_deterministic(target, &GameRules::default())
 }
 
 /*
@@ -133,82 +178,158 @@ fn solve_p1(target: &[usize]) -> usize {
 9 [3, 3, 3]
 */
 
-lazy_static! {
-    static ref DIRAC_ROLLS: Vec<usize> = {
-        let dice = 1..=3;
-        dice.clone()
-            .cartesian_product(dice.clone())
-            .cartesian_product(dice)
-            .map(|vs| vs.0 .0 + vs.0 .1 + vs.1)
-            .collect::<Vec<_>>()
-    };
-}
-
-fn iter_p2(
-    state: &mut HashMap<(Player, Player), usize>,
-    winning_score: usize,
-) -> (HashMap<(Player, Player), usize>, usize, usize) {
-    let mut p1_wins = 0;
-    let mut p2_wins = 0;
-
+fn iter_turn(
+    state: &HashMap<Vec<Player>, usize>,
+    turn: usize,
+    rolls: &[(usize, usize)],
+    rules: &GameRules,
+) -> (HashMap<Vec<Player>, usize>, usize) {
+    let mut wins = 0;
     let mut new_state = HashMap::new();
 
-    state.iter().for_each(|((p1, p2), &count)| {
+    state.iter().for_each(|(players, &count)| {
         // spawn all new universes
-        for p1_roll in DIRAC_ROLLS.iter() {
-            // iter player 1
-            let mut p1 = *p1;
-            p1.position = position(p1.position + p1_roll);
-            p1.score += p1.position;
+        for (roll, universes) in rolls {
+            let mut players = players.clone();
+            players[turn].advance(*roll, rules);
 
-            if p1.score >= winning_score {
+            if players[turn].score >= rules.target_score {
                 // don't add entry, add wins
-                p1_wins += count;
+                wins += count * universes;
                 continue;
             }
 
-            for p2_roll in DIRAC_ROLLS.iter() {
-                // iter player 2
-                let mut p2 = *p2;
-                p2.position = position(p2.position + p2_roll);
-                p2.score += p2.position;
-
-                if p2.score >= winning_score {
-                    // don't add entry, add wins
-                    p2_wins += count;
-                    continue;
-                }
-
-                // else add entry
-                new_state
-                    .entry((p1, p2))
-                    .and_modify(|v| *v += count)
-                    .or_insert(count);
-            }
+            // else add entry
+            *new_state.entry(players).or_insert(0) += count * universes;
         }
     });
 
-    (new_state, p1_wins, p2_wins)
+    (new_state, wins)
 }
 
-fn solve_p2(target: &[usize], winning_score: usize) -> (usize, usize) {
-    let mut state: HashMap<(Player, Player), usize> = HashMap::new();
-    let p1 = Player::new(target[0], 1);
-    let p2 = Player::new(target[1], 2);
+/// how many universes each player wins in
+pub fn count_wins(starts: &[usize], rules: &GameRules) -> Vec<usize> {
+    let rolls = rules.roll_distribution();
+    let mut state = HashMap::new();
+    state.insert(new_players(starts, rules), 1);
 
-    state.entry((p1, p2)).or_insert(1);
+    let mut wins = vec![0; rules.players];
 
-    let mut p1_wins = 0;
-    let mut p2_wins = 0;
+    for turn in (0..rules.players).cycle() {
+        if state.is_empty() {
+            break;
+        }
 
-    while state.iter().map(|(_, count)| *count).sum::<usize>() > 0 {
-        let (new_state, p1, p2) = iter_p2(&mut state, winning_score);
-        p1_wins += p1;
-        p2_wins += p2;
+        let (new_state, turn_wins) = iter_turn(&state, turn, &rolls, rules);
+        wins[turn] += turn_wins;
         state = new_state;
     }
 
-    (p1_wins, p2_wins)
+    wins
+}
+
+fn solve_p2(target: &[usize], winning_score: usize) -> (usize, usize) {
+    let rules = GameRules {
+        target_score: winning_score,
+        ..GameRules::dirac()
+    };
+    let wins = count_wins(target, &rules);
+
+    (wins[0], wins[1])
+}
+
+/// counts universe wins for two players, memoized on the state as seen by the player about to
+/// move: (position, score still needed, other position, other score still needed). keying on the
+/// mover makes the table symmetric, a state with the players swapped is the same entry.
+struct WinCounter {
+    rules: GameRules,
+    rolls: Vec<(usize, usize)>,
+    memo: HashMap<(usize, usize, usize, usize), (usize, usize)>,
+}
+
+impl WinCounter {
+    fn new(rules: &GameRules) -> Self {
+        WinCounter {
+            rules: *rules,
+            rolls: rules.roll_distribution(),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// (mover wins, other wins)
+    fn wins(
+        &mut self,
+        position: usize,
+        needed: usize,
+        other: usize,
+        other_needed: usize,
+    ) -> (usize, usize) {
+        let key = (position, needed, other, other_needed);
+        if let Some(wins) = self.memo.get(&key) {
+            return *wins;
+        }
+
+        let mut wins = (0, 0);
+        for (roll, universes) in self.rolls.clone() {
+            let position = self.rules.position(position + roll);
+
+            if position >= needed {
+                wins.0 += universes;
+            } else {
+                let (other_wins, mover_wins) =
+                    self.wins(other, other_needed, position, needed - position);
+                wins.0 += mover_wins * universes;
+                wins.1 += other_wins * universes;
+            }
+        }
+
+        self.memo.insert(key, wins);
+        wins
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinRow {
+    pub p1_start: usize,
+    pub p2_start: usize,
+    pub target_score: usize,
+    pub p1_wins: usize,
+    pub p2_wins: usize,
+}
+
+/// universe wins for every pair of starting positions and every target score up to
+/// `max_target`, sharing one memo across the whole table. `rules.target_score` is ignored.
+pub fn win_table(rules: &GameRules, max_target: usize) -> Vec<WinRow> {
+    let mut counter = WinCounter::new(rules);
+
+    (1..=rules.board_size)
+        .cartesian_product(1..=rules.board_size)
+        .cartesian_product(1..=max_target)
+        .map(|((p1_start, p2_start), target_score)| {
+            let (p1_wins, p2_wins) = counter.wins(p1_start, target_score, p2_start, target_score);
+            WinRow {
+                p1_start,
+                p2_start,
+                target_score,
+                p1_wins,
+                p2_wins,
+            }
+        })
+        .collect()
+}
+
+pub fn write_win_table_csv<W: Write>(rows: &[WinRow], out: &mut W) -> io::Result<()> {
+    writeln!(out, "p1_start,p2_start,target_score,p1_wins,p2_wins")?;
+
+    for row in rows {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            row.p1_start, row.p2_start, row.target_score, row.p1_wins, row.p2_wins
+        )?;
+    }
+
+    Ok(())
 }
 
 #[aoc_generator(day21)]
@@ -249,5 +370,60 @@ mod tests {
         assert_eq!((655661, 1048978), super::solve_p2(&parsed_input, 8));
         assert_eq!((4008007, 4049420), super::solve_p2(&parsed_input, 9));
         assert_eq!((18973591, 12657100), super::solve_p2(&parsed_input, 10));
+        assert_eq!(
+            vec![444356092776315, 341960390180808],
+            super::count_wins(&parsed_input, &super::GameRules::dirac())
+        );
+    }
+
+    #[test]
+    fn table() {
+        let table = super::win_table(&super::GameRules::dirac(), 21);
+        assert_eq!(10 * 10 * 21, table.len());
+
+        let find = |p1_start, p2_start, target_score| {
+            table
+                .iter()
+                .find(|row| {
+                    (row.p1_start, row.p2_start, row.target_score)
+                        == (p1_start, p2_start, target_score)
+                })
+                .map(|row| (row.p1_wins, row.p2_wins))
+                .unwrap()
+        };
+
+        assert_eq!((27, 0), find(4, 8, 1));
+        assert_eq!((18973591, 12657100), find(4, 8, 10));
+        assert_eq!((444356092776315, 341960390180808), find(4, 8, 21));
+
+        let mut csv = Vec::new();
+        super::write_win_table_csv(&table[..2], &mut csv).unwrap();
+        assert_eq!(
+            "p1_start,p2_start,target_score,p1_wins,p2_wins\n1,1,1,27,0\n1,1,2,27,0\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn custom_rules() {
+        // a one-faced die makes the multiverse collapse into the deterministic game
+        let rules = super::GameRules {
+            board_size: 5,
+            die_faces: 1,
+            rolls_per_turn: 1,
+            players: 3,
+            target_score: 10,
+            deterministic_die: 1,
+        };
+
+        assert_eq!(vec![0, 1, 0], super::count_wins(&[1, 2, 3], &rules));
+        assert_eq!(9 * 8, super::play_deterministic(&[1, 2, 3], &rules));
+
+        let rules = super::GameRules {
+            players: 3,
+            target_score: 1,
+            ..super::GameRules::dirac()
+        };
+        assert_eq!(vec![27, 0, 0], super::count_wins(&[4, 8, 10], &rules));
     }
 }
@@ -1,17 +1,26 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
 
-trait Volume {
+pub trait Volume {
     fn volume(&self) -> isize;
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Range {
     low: isize,
     high: isize,
 }
 
 impl Range {
+    pub fn new(low: isize, high: isize) -> Self {
+        Range { low, high }
+    }
+
     fn len(&self) -> isize {
         self.high - self.low + 1
     }
@@ -20,16 +29,20 @@ impl Range {
         self.low <= other.low && self.high >= other.high
     }
 
-    fn envelopes(&self, other: &Self) -> bool {
-        self.low < other.low && self.high > other.high
-    }
-
     fn intersects(&self, other: &Self) -> bool {
         self.low <= other.high && self.high >= other.low
     }
 
-    fn share_endpoint(&self, other: &Self) -> bool {
-        self.low == other.low || self.high == other.high
+    fn contains(&self, value: isize) -> bool {
+        self.low <= value && value <= self.high
+    }
+
+    /// the smallest range covering both
+    fn hull(&self, other: &Self) -> Self {
+        Range {
+            low: self.low.min(other.low),
+            high: self.high.max(other.high),
+        }
     }
 
     fn union(&self, other: &Self) -> Option<Self> {
@@ -54,179 +67,133 @@ impl Range {
         }
     }
 
-    fn left_difference(&self, other: &Self) -> Option<Self> {
-        if other.encloses(self) || !self.intersects(other) {
-            None
-        } else if self.low < other.low {
+    /// the parts of self below and above other
+    fn difference(&self, other: &Self) -> (Option<Self>, Option<Self>) {
+        let below = if self.low < other.low {
             Some(Range {
                 low: self.low,
-                high: other.low - 1,
+                high: self.high.min(other.low - 1),
             })
-        } else if self.high > other.high {
+        } else {
+            None
+        };
+
+        let above = if self.high > other.high {
             Some(Range {
-                low: other.high + 1,
+                low: self.low.max(other.high + 1),
                 high: self.high,
             })
         } else {
             None
-        }
+        };
+
+        (below, above)
     }
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Cube {
-    x: Range,
-    y: Range,
-    z: Range,
+/// an axis-aligned box in `N` dimensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cuboid<const N: usize> {
+    ranges: [Range; N],
 }
 
-impl Cube {
-    fn envelopes(&self, other: &Self) -> bool {
-        self.x.envelopes(&other.x) && self.y.envelopes(&other.y) && self.z.envelopes(&other.z)
+pub type Cube = Cuboid<3>;
+
+impl<const N: usize> Default for Cuboid<N> {
+    fn default() -> Self {
+        Cuboid {
+            ranges: [Range::default(); N],
+        }
+    }
+}
+
+impl<const N: usize> Cuboid<N> {
+    pub fn new(ranges: [Range; N]) -> Self {
+        Cuboid { ranges }
+    }
+
+    pub fn ranges(&self) -> &[Range; N] {
+        &self.ranges
+    }
+
+    fn contains(&self, point: [isize; N]) -> bool {
+        self.ranges
+            .iter()
+            .zip(point)
+            .all(|(range, value)| range.contains(value))
     }
 
     fn encloses(&self, other: &Self) -> bool {
-        self.x.encloses(&other.x) && self.y.encloses(&other.y) && self.z.encloses(&other.z)
+        self.ranges
+            .iter()
+            .zip(&other.ranges)
+            .all(|(range, other)| range.encloses(other))
     }
 
     fn intersects(&self, other: &Self) -> bool {
-        self.x.intersects(&other.x) && self.y.intersects(&other.y) && self.z.intersects(&other.z)
+        self.ranges
+            .iter()
+            .zip(&other.ranges)
+            .all(|(range, other)| range.intersects(other))
     }
 
     fn intersection(&self, other: &Self) -> Option<Self> {
         if self.intersects(other) {
-            let x = self.x.intersection(&other.x).unwrap();
-            let y = self.y.intersection(&other.y).unwrap();
-            let z = self.z.intersection(&other.z).unwrap();
-            Some(Cube { x, y, z })
+            let mut ranges = self.ranges;
+            ranges
+                .iter_mut()
+                .zip(&other.ranges)
+                .for_each(|(range, other)| *range = range.intersection(other).unwrap());
+            Some(Cuboid { ranges })
         } else {
             None
         }
     }
 
-    fn share_corner(&self, other: &Self) -> bool {
-        self.x.share_endpoint(&other.x)
-            && self.y.share_endpoint(&other.y)
-            && self.z.share_endpoint(&other.z)
+    /// the smallest cuboid covering both
+    fn hull(&self, other: &Self) -> Self {
+        let mut ranges = self.ranges;
+        ranges
+            .iter_mut()
+            .zip(&other.ranges)
+            .for_each(|(range, other)| *range = range.hull(other));
+        Cuboid { ranges }
     }
 
+    /// splits self minus other into at most `2 * N` disjoint slabs. other must be enclosed.
     fn remove(&self, other: &Self) -> Vec<Self> {
-        let mut other = *other;
-        // println!("{} {:?} {:?}", self.intersects(&other), self, other);
-        let mut cubes = if !self.encloses(&other) {
-            Vec::new()
-        } else if !self.share_corner(&other) {
-            // println!("these don't share a corner: {:?} {:?}", self, other);
-            // special case: other is not anchored to a corner
-            //      create inner cube > other
-            //      collect cubes
-            //      is this a recursive case?
-            //          expand other to one corner and then remove
-            //          remove other from expanded case
-            let x = Range {
-                low: self.x.low,
-                high: other.x.high,
-            };
-            let y = Range {
-                low: self.y.low,
-                high: other.y.high,
-            };
-            let z = Range {
-                low: self.z.low,
-                high: other.z.high,
-            };
-            let inner_cube = Cube { x, y, z };
-
-            let cubes = inner_cube.remove(&other);
-
-            other = inner_cube;
-
-            // println!("cube within cube detected -\ninner: {:?}\nouter: {:?}\ninner_cube: {:?}\nresult: {:?}", other, self, inner_cube, cubes);
-
-            cubes
-        } else {
-            Vec::new()
-        };
-
-        // split into 7 cubes
-        //      intersecting on not x and not y and not z
-        //      intersecting on x and not y and not z
-        //      intersecting on y and not z and not x
-        //      intersecting on z and not x and not y
-        //      intersecting on x and y and not z
-        //      intersecting on y and z and not x
-        //      intersecting on z and x and not y
-        let x_intersection = self.x.intersection(&other.x).unwrap();
-        let y_intersection = self.y.intersection(&other.y).unwrap();
-        let z_intersection = self.z.intersection(&other.z).unwrap();
-
-        let x_diff = self.x.left_difference(&other.x);
-        let y_diff = self.y.left_difference(&other.y);
-        let z_diff = self.z.left_difference(&other.z);
-
-        if let Some(x) = x_diff {
-            cubes.push(Cube {
-                x,
-                y: y_intersection,
-                z: z_intersection,
-            });
+        if !self.encloses(other) {
+            return Vec::new();
         }
 
-        if let Some(y) = y_diff {
-            cubes.push(Cube {
-                x: x_intersection,
-                y,
-                z: z_intersection,
-            });
-        }
+        // peel off the parts outside other one axis at a time, narrowing the remainder to
+        // other's range on that axis as we go
+        let mut remainder = *self;
+        let mut cuboids = Vec::new();
 
-        if let Some(z) = z_diff {
-            cubes.push(Cube {
-                x: x_intersection,
-                y: y_intersection,
-                z,
-            });
-        }
+        for axis in 0..N {
+            let (below, above) = remainder.ranges[axis].difference(&other.ranges[axis]);
 
-        if let (Some(x), Some(y)) = (x_diff, y_diff) {
-            cubes.push(Cube {
-                x,
-                y,
-                z: z_intersection,
-            });
-        }
-
-        if let (Some(x), Some(z)) = (x_diff, z_diff) {
-            cubes.push(Cube {
-                x,
-                y: y_intersection,
-                z,
-            });
-        }
-
-        if let (Some(y), Some(z)) = (y_diff, z_diff) {
-            cubes.push(Cube {
-                x: x_intersection,
-                y,
-                z,
-            });
-        }
+            for slab in [below, above].into_iter().flatten() {
+                let mut cuboid = remainder;
+                cuboid.ranges[axis] = slab;
+                cuboids.push(cuboid);
+            }
 
-        if let (Some(x), Some(y), Some(z)) = (x_diff, y_diff, z_diff) {
-            cubes.push(Cube { x, y, z });
+            remainder.ranges[axis] = other.ranges[axis];
         }
 
-        cubes
+        cuboids
     }
 }
 
-impl Volume for Cube {
+impl<const N: usize> Volume for Cuboid<N> {
     fn volume(&self) -> isize {
-        self.x.len() * self.y.len() * self.z.len()
+        self.ranges.iter().map(|range| range.len()).product()
     }
 }
 
-impl Volume for Vec<Cube> {
+impl<const N: usize> Volume for Vec<Cuboid<N>> {
     fn volume(&self) -> isize {
         self.iter().map(|cube| cube.volume()).sum()
     }
@@ -240,151 +207,425 @@ fn parse_range(input: &str) -> Range {
     }
 }
 
-fn parse_cube(input: &str) -> Cube {
-    let mut cube: Cube = Default::default();
-    input.split(',').for_each(|tagged_range| {
-        let (tag, range) = tagged_range.split_once('=').unwrap();
-        let range = parse_range(range);
-        match tag {
-            "x" => cube.x = range,
-            "y" => cube.y = range,
-            "z" => cube.z = range,
-            _ => unreachable!(),
-        }
-    });
+/// axes are ordered x, y, z, w and then any other tags alphabetically
+fn axis_order(tag: &str) -> (usize, &str) {
+    match tag {
+        "x" => (0, tag),
+        "y" => (1, tag),
+        "z" => (2, tag),
+        "w" => (3, tag),
+        _ => (4, tag),
+    }
+}
+
+fn parse_cuboid<const N: usize>(input: &str) -> (Vec<String>, Cuboid<N>) {
+    let mut tagged_ranges = input
+        .split(',')
+        .map(|tagged_range| {
+            let (tag, range) = tagged_range.split_once('=').unwrap();
+            (tag, parse_range(range))
+        })
+        .collect::<Vec<_>>();
+    tagged_ranges.sort_unstable_by_key(|(tag, _)| axis_order(tag));
 
-    cube
+    assert_eq!(N, tagged_ranges.len(), "expected {} axes in {:?}", N, input);
+
+    let mut cuboid: Cuboid<N> = Default::default();
+    tagged_ranges
+        .iter()
+        .enumerate()
+        .for_each(|(axis, (_, range))| cuboid.ranges[axis] = *range);
+
+    let tags = tagged_ranges
+        .into_iter()
+        .map(|(tag, _)| tag.to_string())
+        .collect();
+    (tags, cuboid)
 }
 
-fn parse_input(input: &str) -> Vec<(bool, Cube)> {
-    input
+/// parses reboot steps over any `N` axis tags, returning the tags in axis order. every step has
+/// to use the same tags.
+pub fn parse_steps<const N: usize>(input: &str) -> (Vec<String>, Vec<(bool, Cuboid<N>)>) {
+    let mut axes: Option<Vec<String>> = None;
+
+    let steps = input
         .lines()
         .map(|line| {
             let (state, cube) = line.split_once(' ').unwrap();
             let state = matches!(state, "on");
-            let cube = parse_cube(cube);
+            let (tags, cube) = parse_cuboid(cube);
+
+            match &axes {
+                Some(axes) => assert_eq!(axes, &tags, "mismatched axes in {:?}", line),
+                None => axes = Some(tags),
+            }
+
             (state, cube)
         })
-        .collect()
+        .collect();
+
+    (axes.unwrap_or_default(), steps)
 }
 
-fn count_cubes(input: &[(bool, Cube)]) -> isize {
-    input
-        .iter()
-        .fold(Vec::<Cube>::new(), |agg, (state, new_cube)| {
-            // println!(
-            //     "{} {:?}",
-            //     if *state { "adding" } else { "removing" },
-            //     new_cube
-            // );
-            // println!("state: {}; volume: {}", agg.len(), agg.volume());
-            // println!("new: {:?}", new_cube);
-
-            // we guarantee each iteration that all cubes are distinct.
-            // agg.iter()
-            //     .enumerate()
-            //     .tuple_combinations()
-            //     .for_each(|(a, b)| {
-            //         // println!("{:?}", a.0);
-            //         // println!("{:?}", b.0);
-
-            //         let intersection = a.1.intersection(b.1);
-            //         // println!("{:?}", intersection);
-            //         assert!(intersection.is_none(), "agg error: {:?} {:?}", a, b);
-            //     });
-
-            // with that assumption, all remove operations are distinct
-            let mut agg: Vec<Cube> = agg
-                .iter()
-                .flat_map(|cube| {
-                    if let Some(intersection) = cube.intersection(new_cube) {
-                        // println!("{:?} {:?}", cube, intersection);
-                        let mut removed1 = cube.remove(&intersection);
-
-                        if *state {
-                            removed1.push(intersection);
-                        }
+fn parse_input(input: &str) -> Vec<(bool, Cube)> {
+    parse_steps(input).1
+}
 
-                        removed1
-                    } else {
-                        vec![*cube]
-                    }
-                })
-                .collect();
-
-            // if this is an `on`, then add the new cube minus all intersections with other cubes
-            if *state {
-                // println!("reducing: {:?}", new_cube);
-
-                let mut new_cubes = agg.iter().fold(vec![*new_cube], |new_cubes, cube| {
-                    // new_cubes
-                    //     .iter()
-                    //     .enumerate()
-                    //     .tuple_combinations()
-                    //     .for_each(|(a, b)| {
-                    //         let intersection = a.1.intersection(b.1);
-                    //         // println!("{:?}", intersection);
-                    //         assert!(intersection.is_none(), "new_cube error: {:?} {:?}", a, b);
-                    //     });
-
-                    let new_cube = new_cubes
-                        .iter()
-                        .flat_map(|new_cube| {
-                            // assert_eq!(cube.intersection(new_cube), new_cube.intersection(&cube));
-                            if let Some(intersection) = new_cube.intersection(cube) {
-                                let mut removed = new_cube.remove(&intersection);
-                                // removed.sort_unstable();
-                                // println!(
-                                //     "intersection detected between {:?} and {:?}, intersection: {:?}, new: {:?}",
-                                //     new_cube, cube, intersection, removed
-                                // );
-                                // assert_eq!(
-                                //     new_cube.volume(),
-                                //     removed.volume() + intersection.volume()
-                                // );
-                                removed
-                            } else {
-                                vec![*new_cube]
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                    if new_cube.len() != new_cubes.len() {
-                        // println!("after removing {:?}:\n{:?}", cube, new_cube);
+/// the reactor state as a set of disjoint cubes that are on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reactor<const N: usize = 3> {
+    cubes: Vec<Cuboid<N>>,
+}
+
+impl<const N: usize> Default for Reactor<N> {
+    fn default() -> Self {
+        Reactor { cubes: Vec::new() }
+    }
+}
+
+impl<const N: usize> Reactor<N> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cubes(&self) -> &[Cuboid<N>] {
+        &self.cubes
+    }
+
+    pub fn apply(&mut self, (state, new_cube): &(bool, Cuboid<N>)) {
+        // we guarantee each iteration that all cubes are distinct.
+        // with that assumption, all remove operations are distinct
+        let mut agg: Vec<Cuboid<N>> = self
+            .cubes
+            .iter()
+            .flat_map(|cube| {
+                if let Some(intersection) = cube.intersection(new_cube) {
+                    let mut removed = cube.remove(&intersection);
+
+                    if *state {
+                        removed.push(intersection);
                     }
-                    new_cube
-                });
 
-                // println!("added cubes w/o dedup: {}", new_cubes.len());
+                    removed
+                } else {
+                    vec![*cube]
+                }
+            })
+            .collect();
+
+        // if this is an `on`, then add the new cube minus all intersections with other cubes
+        if *state {
+            let new_cubes = agg.iter().fold(vec![*new_cube], |new_cubes, cube| {
+                new_cubes
+                    .iter()
+                    .flat_map(|new_cube| {
+                        if let Some(intersection) = new_cube.intersection(cube) {
+                            new_cube.remove(&intersection)
+                        } else {
+                            vec![*new_cube]
+                        }
+                    })
+                    .collect()
+            });
+
+            agg.extend(new_cubes);
+        }
+
+        self.cubes = agg;
+    }
+
+    pub fn is_on(&self, point: [isize; N]) -> bool {
+        self.cubes.iter().any(|cube| cube.contains(point))
+    }
+
+    pub fn volume_in(&self, region: &Cuboid<N>) -> isize {
+        self.cubes
+            .iter()
+            .filter_map(|cube| cube.intersection(region))
+            .map(|cube| cube.volume())
+            .sum()
+    }
+
+    pub fn bounding_box(&self) -> Option<Cuboid<N>> {
+        self.cubes
+            .iter()
+            .copied()
+            .reduce(|bounds, cube| bounds.hull(&cube))
+    }
+}
+
+impl<const N: usize> Volume for Reactor<N> {
+    fn volume(&self) -> isize {
+        self.cubes.volume()
+    }
+}
 
-                // new_cubes.sort_unstable();
-                // new_cubes.dedup();
+/// an outward facing square-ish patch of the reactor surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Face {
+    axis: usize,
+    /// position of the face along `axis`, on the grid lines between cubes
+    plane: isize,
+    /// whether the face points towards +`axis`
+    positive: bool,
+    /// extent over the next two axes in cyclic order
+    rect: Cuboid<2>,
+}
+
+impl Reactor<3> {
+    /// faces on the outside of the on region. faces of touching cubes cancel out on a shared
+    /// plane, so only the outer surface is left.
+    fn surface(&self) -> Vec<Face> {
+        let mut planes = BTreeMap::<(usize, isize), (Vec<Cuboid<2>>, Vec<Cuboid<2>>)>::new();
+
+        self.cubes.iter().for_each(|cube| {
+            (0..3).for_each(|axis| {
+                let range = cube.ranges[axis];
+                let rect = Cuboid::new([cube.ranges[(axis + 1) % 3], cube.ranges[(axis + 2) % 3]]);
+
+                planes
+                    .entry((axis, range.high + 1))
+                    .or_default()
+                    .0
+                    .push(rect);
+                planes.entry((axis, range.low)).or_default().1.push(rect);
+            });
+        });
+
+        planes
+            .into_iter()
+            .flat_map(|((axis, plane), (positive, negative))| {
+                let exposed = |faces: &[Cuboid<2>], covers: &[Cuboid<2>]| {
+                    let mut rects = Reactor::<2>::new();
+                    faces.iter().for_each(|rect| rects.apply(&(true, *rect)));
+                    covers.iter().for_each(|rect| rects.apply(&(false, *rect)));
+                    rects.cubes
+                };
+
+                exposed(&positive, &negative)
+                    .into_iter()
+                    .map(move |rect| (true, rect))
+                    .chain(
+                        exposed(&negative, &positive)
+                            .into_iter()
+                            .map(move |rect| (false, rect)),
+                    )
+                    .map(move |(positive, rect)| Face {
+                        axis,
+                        plane,
+                        positive,
+                        rect,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
 
-                // println!("added cubes: {}", new_cubes.len());
+    pub fn surface_area(&self) -> isize {
+        self.surface().iter().map(|face| face.rect.volume()).sum()
+    }
 
-                agg.extend(new_cubes);
+    /// writes the outer surface as a wavefront obj mesh, one quad per exposed face
+    pub fn write_obj<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut vertices = HashMap::new();
+        let mut quads = Vec::new();
+
+        for face in self.surface() {
+            let [b, c] = face.rect.ranges;
+            let mut corners = [
+                (b.low, c.low),
+                (b.high + 1, c.low),
+                (b.high + 1, c.high + 1),
+                (b.low, c.high + 1),
+            ];
+            // counter-clockwise when seen from outside
+            if !face.positive {
+                corners.reverse();
             }
 
-            agg
+            let quad = corners.map(|(b, c)| {
+                let mut vertex = [0; 3];
+                vertex[face.axis] = face.plane;
+                vertex[(face.axis + 1) % 3] = b;
+                vertex[(face.axis + 2) % 3] = c;
+
+                let next = vertices.len() + 1;
+                *vertices.entry(vertex).or_insert(next)
+            });
+            quads.push(quad);
+        }
+
+        let mut vertices = vertices.into_iter().collect::<Vec<_>>();
+        vertices.sort_unstable_by_key(|(_, index)| *index);
+
+        for ([x, y, z], _) in vertices {
+            writeln!(out, "v {} {} {}", x, y, z)?;
+        }
+        for [a, b, c, d] in quads {
+            writeln!(out, "f {} {} {} {}", a, b, c, d)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// the ways we know how to count the cubes left on after a reboot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// split cubes into disjoint pieces on every overlap, see `Reactor`
+    Disjoint,
+    /// keep signed cubes, cancelling every overlap with a cube of the opposite sign
+    InclusionExclusion,
+    /// paint an on/off bitmap over the compressed coordinates. memory grows with the cube of the
+    /// step count, so this is only for small inputs.
+    Compressed,
+    /// run every backend and assert they agree
+    CrossCheck,
+}
+
+fn count_cubes<const N: usize>(input: &[(bool, Cuboid<N>)]) -> isize {
+    input
+        .iter()
+        .fold(Reactor::new(), |mut reactor, step| {
+            reactor.apply(step);
+            reactor
         })
         .volume()
 }
 
-fn solve_p1(input: &[(bool, Cube)]) -> isize {
-    let view = Cube {
-        x: Range { low: -50, high: 50 },
-        y: Range { low: -50, high: 50 },
-        z: Range { low: -50, high: 50 },
+fn count_signed_cubes<const N: usize>(input: &[(bool, Cuboid<N>)]) -> isize {
+    input
+        .iter()
+        .fold(
+            HashMap::<Cuboid<N>, isize>::new(),
+            |mut signed, (state, new_cube)| {
+                let overlaps = signed
+                    .iter()
+                    .filter_map(|(cube, sign)| {
+                        cube.intersection(new_cube).map(|cube| (cube, *sign))
+                    })
+                    .collect::<Vec<_>>();
+
+                overlaps.into_iter().for_each(|(cube, sign)| {
+                    *signed.entry(cube).or_default() -= sign;
+                });
+
+                if *state {
+                    *signed.entry(*new_cube).or_default() += 1;
+                }
+
+                signed.retain(|_, sign| *sign != 0);
+                signed
+            },
+        )
+        .iter()
+        .map(|(cube, sign)| cube.volume() * sign)
+        .sum()
+}
+
+/// sorted, deduplicated boundaries along one axis. cell `i` spans `bounds[i]..bounds[i + 1]`.
+fn compress(ranges: impl Iterator<Item = Range>) -> Vec<isize> {
+    let mut bounds = ranges
+        .flat_map(|range| [range.low, range.high + 1])
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds
+}
+
+fn cell_span(bounds: &[isize], range: &Range) -> std::ops::Range<usize> {
+    let start = bounds.binary_search(&range.low).unwrap();
+    let end = bounds.binary_search(&(range.high + 1)).unwrap();
+    start..end
+}
+
+fn count_compressed_cubes<const N: usize>(input: &[(bool, Cuboid<N>)]) -> isize {
+    let bounds = (0..N)
+        .map(|axis| compress(input.iter().map(|(_, cube)| cube.ranges[axis])))
+        .collect::<Vec<_>>();
+    let cells = bounds
+        .iter()
+        .map(|bounds| bounds.len().saturating_sub(1))
+        .collect::<Vec<_>>();
+
+    // row-major cell index, so the last axis is contiguous
+    let index = |cell: &[usize]| {
+        cell.iter()
+            .zip(&cells)
+            .fold(0, |index, (cell, count)| index * count + cell)
     };
 
+    let mut bitmap = vec![0u64; cells.iter().product::<usize>().div_ceil(64)];
+
+    input.iter().for_each(|(state, cube)| {
+        cube.ranges
+            .iter()
+            .zip(&bounds)
+            .map(|(range, bounds)| cell_span(bounds, range))
+            .multi_cartesian_product()
+            .for_each(|cell| {
+                let i = index(&cell);
+                if *state {
+                    bitmap[i / 64] |= 1 << (i % 64);
+                } else {
+                    bitmap[i / 64] &= !(1 << (i % 64));
+                }
+            });
+    });
+
+    cells
+        .iter()
+        .map(|count| 0..*count)
+        .multi_cartesian_product()
+        .filter(|cell| {
+            let i = index(cell);
+            bitmap[i / 64] & (1 << (i % 64)) != 0
+        })
+        .map(|cell| {
+            cell.iter()
+                .zip(&bounds)
+                .map(|(cell, bounds)| bounds[cell + 1] - bounds[*cell])
+                .product::<isize>()
+        })
+        .sum()
+}
+
+pub fn count_cubes_with<const N: usize>(input: &[(bool, Cuboid<N>)], backend: Backend) -> isize {
+    match backend {
+        Backend::Disjoint => count_cubes(input),
+        Backend::InclusionExclusion => count_signed_cubes(input),
+        Backend::Compressed => count_compressed_cubes(input),
+        Backend::CrossCheck => {
+            let disjoint = count_cubes(input);
+            let signed = count_signed_cubes(input);
+            let compressed = count_compressed_cubes(input);
+
+            assert_eq!(
+                disjoint, signed,
+                "inclusion-exclusion disagrees with disjoint cubes"
+            );
+            assert_eq!(
+                disjoint, compressed,
+                "compressed bitmap disagrees with disjoint cubes"
+            );
+
+            disjoint
+        }
+    }
+}
+
+fn solve_p1(input: &[(bool, Cube)], backend: Backend) -> isize {
+    let view = Cube::new([Range { low: -50, high: 50 }; 3]);
+
     let input = input
         .iter()
         .filter_map(|(state, cube)| view.intersection(cube).map(|cube| (*state, cube)))
         .collect::<Vec<_>>();
-    count_cubes(&input)
+    count_cubes_with(&input, backend)
 }
 
-fn solve_p2(input: &[(bool, Cube)]) -> isize {
-    count_cubes(input)
+fn solve_p2(input: &[(bool, Cube)], backend: Backend) -> isize {
+    count_cubes_with(input, backend)
 }
 
 #[aoc_generator(day22)]
@@ -394,17 +635,17 @@ pub fn input_generator(input: &str) -> Vec<(bool, Cube)> {
 
 #[aoc(day22, part1)]
 pub fn wrapper_p1(input: &[(bool, Cube)]) -> isize {
-    solve_p1(input)
+    solve_p1(input, Backend::Disjoint)
 }
 
 #[aoc(day22, part2)]
 pub fn wrapper_p2(input: &[(bool, Cube)]) -> isize {
-    solve_p2(input)
+    solve_p2(input, Backend::Disjoint)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day22::{Cube, Range, Volume};
+    use crate::day22::{Backend, Cube, Range, Reactor, Volume};
 
     #[test]
     fn test_range() {
@@ -417,24 +658,24 @@ mod tests {
             range1.intersection(&range2)
         );
         assert_eq!(
-            Some(Range { low: 10, high: 10 }),
-            range1.left_difference(&range2)
+            (Some(Range { low: 10, high: 10 }), None),
+            range1.difference(&range2)
         );
     }
 
     #[test]
     fn test_cube() {
-        let cube1 = Cube {
-            x: Range { low: 11, high: 12 },
-            y: Range { low: 10, high: 10 },
-            z: Range { low: 10, high: 12 },
-        };
-
-        let cube2 = Cube {
-            x: Range { low: 11, high: 12 },
-            y: Range { low: 11, high: 12 },
-            z: Range { low: 11, high: 12 },
-        };
+        let cube1 = Cube::new([
+            Range { low: 11, high: 12 },
+            Range { low: 10, high: 10 },
+            Range { low: 10, high: 12 },
+        ]);
+
+        let cube2 = Cube::new([
+            Range { low: 11, high: 12 },
+            Range { low: 11, high: 12 },
+            Range { low: 11, high: 12 },
+        ]);
 
         assert_eq!(6, cube1.volume());
         assert_eq!(8, cube2.volume());
@@ -453,6 +694,84 @@ mod tests {
         println!("{:?}", removed2.map(|cube| cube.volume()));
     }
 
+    #[test]
+    fn test_reactor() {
+        let steps = super::input_generator(
+            "on x=10..12,y=10..12,z=10..12\non x=11..13,y=11..13,z=11..13\noff x=9..11,y=9..11,z=9..11\non x=10..10,y=10..10,z=10..10",
+        );
+        let mut reactor = Reactor::new();
+
+        reactor.apply(&steps[0]);
+        assert_eq!(27, reactor.volume());
+        assert!(reactor.is_on([10, 10, 10]));
+
+        reactor.apply(&steps[1]);
+        assert_eq!(46, reactor.volume());
+        assert!(reactor.is_on([13, 13, 13]));
+
+        reactor.apply(&steps[2]);
+        assert_eq!(38, reactor.volume());
+        assert!(!reactor.is_on([10, 10, 10]));
+        assert!(reactor.is_on([12, 12, 12]));
+
+        reactor.apply(&steps[3]);
+        assert_eq!(39, reactor.volume());
+        assert!(reactor.is_on([10, 10, 10]));
+
+        let region = Cube::new([Range::new(10, 11); 3]);
+        assert_eq!(1, reactor.volume_in(&region));
+        assert_eq!(
+            Some(Cube::new([Range::new(10, 13); 3])),
+            reactor.bounding_box()
+        );
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let (axes, steps) = super::parse_steps::<2>("on x=0..9,y=0..9\noff y=5..14,x=5..14");
+        assert_eq!(vec!["x", "y"], axes);
+        assert_eq!(75, super::count_cubes_with(&steps, Backend::CrossCheck));
+
+        let mut reactor = Reactor::new();
+        steps.iter().for_each(|step| reactor.apply(step));
+        assert!(reactor.is_on([4, 9]));
+        assert!(!reactor.is_on([5, 5]));
+
+        let (axes, steps) = super::parse_steps::<4>(
+            "on x=0..1,y=0..1,z=0..1,w=0..1\non w=1..2,x=1..2,y=1..2,z=1..2",
+        );
+        assert_eq!(vec!["x", "y", "z", "w"], axes);
+        assert_eq!(31, super::count_cubes_with(&steps, Backend::CrossCheck));
+    }
+
+    #[test]
+    fn test_surface() {
+        let steps = super::input_generator("on x=0..0,y=0..0,z=0..0\non x=1..1,y=0..0,z=0..0");
+        let mut reactor = Reactor::new();
+        steps.iter().for_each(|step| reactor.apply(step));
+
+        assert_eq!(10, reactor.surface_area());
+
+        let mut obj = Vec::new();
+        reactor.write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(
+            12,
+            obj.lines().filter(|line| line.starts_with("v ")).count()
+        );
+        assert_eq!(
+            10,
+            obj.lines().filter(|line| line.starts_with("f ")).count()
+        );
+
+        // a hole punched through the middle adds its walls to the surface
+        let steps = super::input_generator("on x=0..2,y=0..2,z=0..2\noff x=1..1,y=1..1,z=0..2");
+        let mut reactor = Reactor::new();
+        steps.iter().for_each(|step| reactor.apply(step));
+
+        assert_eq!(54 - 2 + 12, reactor.surface_area());
+    }
+
     #[test]
     fn test_p1() {
         let inputs = vec![
@@ -463,7 +782,8 @@ mod tests {
 
         for (input, expect) in inputs {
             let parsed_input = super::input_generator(input);
-            assert_eq!(expect, super::solve_p1(&parsed_input));
+            assert_eq!(expect, super::solve_p1(&parsed_input, Backend::Disjoint));
+            assert_eq!(expect, super::solve_p1(&parsed_input, Backend::CrossCheck));
         }
     }
 
@@ -475,7 +795,8 @@ mod tests {
 
         for (input, expect) in inputs {
             let parsed_input = super::input_generator(input);
-            assert_eq!(expect, super::solve_p2(&parsed_input));
+            assert_eq!(expect, super::solve_p2(&parsed_input, Backend::Disjoint));
+            assert_eq!(expect, super::solve_p2(&parsed_input, Backend::CrossCheck));
         }
     }
 }
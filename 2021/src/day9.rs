@@ -1,4 +1,7 @@
+use std::io::{self, Write};
+
 use aoc_runner_derive::{aoc, aoc_generator};
+use itertools::Itertools;
 use nalgebra::DMatrix;
 
 fn parse_input(input: &str) -> DMatrix<u8> {
@@ -51,39 +54,170 @@ fn solve_p1(input: &DMatrix<u8>) -> usize {
         .sum()
 }
 
-fn basin_size(map: &DMatrix<u8>, source: (usize, usize)) -> usize {
-    let mut seen = Vec::new();
-    let mut last_layer = vec![source];
+/// which cells separate basins and which cells flow into each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasinRules {
+    /// cells at least this high belong to no basin
+    pub barrier: u8,
+    /// diagonal neighbours share a basin as well as orthogonal ones
+    pub diagonal: bool,
+}
 
-    while !last_layer.is_empty() {
-        let mut next_layer: Vec<(usize, usize)> = last_layer
-            .iter()
-            .flat_map(|coord| {
-                surrounding(*coord)
-                    .into_iter()
-                    .filter(|coord| !seen.contains(coord) && map[*coord] < 9)
-            })
-            .collect();
+impl Default for BasinRules {
+    fn default() -> Self {
+        BasinRules {
+            barrier: 9,
+            diagonal: false,
+        }
+    }
+}
+
+/// a basin with its cells in scan order, as (row, col) on the unpadded map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Basin {
+    pub cells: Vec<(usize, usize)>,
+    /// the lowest cell, the first in scan order on ties
+    pub low_point: (usize, usize),
+}
+
+impl Basin {
+    pub fn size(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+/// every basin of the map along with each cell's basin label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasinMap {
+    labels: DMatrix<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+struct DisjointSet {
+    parent: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parent[node] != node {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+        node
+    }
+
+    fn union(&mut self, first: usize, second: usize) {
+        let (first, second) = (self.find(first), self.find(second));
+        self.parent[first.max(second)] = first.min(second);
+    }
+}
+
+impl BasinMap {
+    /// labels every basin in a single union-find pass over the padded height map.
+    pub fn new(map: &DMatrix<u8>, rules: BasinRules) -> Self {
+        let (rows, cols) = (map.nrows() - 2, map.ncols() - 2);
+        let open =
+            |(i, j): (usize, usize)| i < rows && j < cols && map[(i + 1, j + 1)] < rules.barrier;
+
+        let mut set = DisjointSet {
+            parent: (0..rows * cols).collect(),
+        };
+        for i in 0..rows {
+            for j in 0..cols {
+                if !open((i, j)) {
+                    continue;
+                }
+
+                // only neighbours already scanned, the rest link back to this cell later
+                let mut earlier = vec![(i.wrapping_sub(1), j), (i, j.wrapping_sub(1))];
+                if rules.diagonal {
+                    earlier.push((i.wrapping_sub(1), j.wrapping_sub(1)));
+                    earlier.push((i.wrapping_sub(1), j + 1));
+                }
+                for (ni, nj) in earlier.into_iter().filter(|&coord| open(coord)) {
+                    set.union(i * cols + j, ni * cols + nj);
+                }
+            }
+        }
+
+        let mut labels = DMatrix::from_element(rows, cols, None);
+        let mut root_labels = vec![None; rows * cols];
+        let mut basins = Vec::<Basin>::new();
+        for i in 0..rows {
+            for j in 0..cols {
+                if !open((i, j)) {
+                    continue;
+                }
 
-        next_layer.sort_unstable();
-        next_layer.dedup();
+                let root = set.find(i * cols + j);
+                let label = *root_labels[root].get_or_insert_with(|| {
+                    basins.push(Basin {
+                        cells: Vec::new(),
+                        low_point: (i, j),
+                    });
+                    basins.len() - 1
+                });
+
+                let basin = &mut basins[label];
+                basin.cells.push((i, j));
+                let (li, lj) = basin.low_point;
+                if map[(i + 1, j + 1)] < map[(li + 1, lj + 1)] {
+                    basin.low_point = (i, j);
+                }
+                labels[(i, j)] = Some(label);
+            }
+        }
+
+        BasinMap { labels, basins }
+    }
 
-        seen.append(&mut last_layer);
-        last_layer.append(&mut next_layer);
+    pub fn basins(&self) -> &[Basin] {
+        &self.basins
+    }
+
+    /// the basin label of an unpadded (row, col), none for barriers
+    pub fn label(&self, coord: (usize, usize)) -> Option<usize> {
+        self.labels[coord]
+    }
+
+    /// one char per cell: `#` for barriers, basins cycle through letters and digits.
+    pub fn render_ascii(&self) -> String {
+        const SYMBOLS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+        self.labels
+            .row_iter()
+            .map(|row| {
+                row.iter()
+                    .map(|label| match label {
+                        Some(label) => char::from(SYMBOLS[label % SYMBOLS.len()]),
+                        None => '#',
+                    })
+                    .collect::<String>()
+            })
+            .join("\n")
     }
 
-    seen.len()
+    /// writes the basin map as a binary greymap, `scale` pixels per cell. barriers are black and
+    /// each basin gets a grey level picked from its label.
+    pub fn write_pgm<W: Write>(&self, out: &mut W, scale: usize) -> io::Result<()> {
+        let (rows, cols) = (self.labels.nrows() * scale, self.labels.ncols() * scale);
+        let pixels = (0..rows)
+            .flat_map(|y| (0..cols).map(move |x| self.labels[(y / scale, x / scale)]))
+            .map(|label| label.map_or(0, |label| 64 + (label * 67 % 192) as u8))
+            .collect::<Vec<_>>();
+
+        write!(out, "P5\n{} {}\n255\n", cols, rows)?;
+        out.write_all(&pixels)
+    }
 }
 
 fn solve_p2(input: &DMatrix<u8>) -> usize {
-    let mut basins: Vec<usize> = get_low_points(input)
-        .into_iter()
-        .map(|coord| basin_size(input, coord))
-        .collect();
+    let basins = BasinMap::new(input, BasinRules::default());
+    let mut sizes: Vec<usize> = basins.basins().iter().map(Basin::size).collect();
 
-    basins.sort_unstable();
-    basins.reverse();
-    basins.iter().take(3).product()
+    sizes.sort_unstable();
+    sizes.reverse();
+    sizes.iter().take(3).product()
 }
 
 #[aoc_generator(day9)]
@@ -111,4 +245,55 @@ mod tests {
         assert_eq!(15, super::solve_p1(&parsed_input));
         assert_eq!(1134, super::solve_p2(&parsed_input));
     }
+
+    #[test]
+    fn basin_map() {
+        use super::{BasinMap, BasinRules};
+
+        let input = "2199943210\n3987894921\n9856789892\n8767896789\n9899965678";
+        let parsed_input = super::input_generator(input);
+
+        let map = BasinMap::new(&parsed_input, BasinRules::default());
+        let mut sizes = map
+            .basins()
+            .iter()
+            .map(|basin| basin.size())
+            .collect::<Vec<_>>();
+        sizes.sort_unstable();
+        assert_eq!(vec![3, 9, 9, 14], sizes);
+        assert_eq!((0, 1), map.basins()[0].low_point);
+        assert_eq!(Some(0), map.label((1, 0)));
+        assert_eq!(None, map.label((0, 2)));
+        assert_eq!(
+            "aa###bbbbb\na#ccc#b#bb\n#ccccc#d#b\nccccc#ddd#\n#c###ddddd",
+            map.render_ascii()
+        );
+
+        // without barriers everything is one basin, diagonals merge the basins too
+        let rules = BasinRules {
+            barrier: 10,
+            diagonal: false,
+        };
+        let map = BasinMap::new(&parsed_input, rules);
+        assert_eq!(
+            vec![50],
+            map.basins()
+                .iter()
+                .map(|basin| basin.size())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!((0, 9), map.basins()[0].low_point);
+        let rules = BasinRules {
+            barrier: 9,
+            diagonal: true,
+        };
+        assert_eq!(1, BasinMap::new(&parsed_input, rules).basins().len());
+
+        let map = BasinMap::new(&parsed_input, BasinRules::default());
+        let mut pgm = Vec::new();
+        map.write_pgm(&mut pgm, 2).unwrap();
+        assert!(pgm.starts_with(b"P5\n20 10\n255\n"));
+        assert_eq!(b"P5\n20 10\n255\n".len() + 20 * 10, pgm.len());
+        assert_eq!(0, pgm[pgm.len() - 20 * 10 + 4]);
+    }
 }
@@ -5,19 +5,19 @@ use aoc_runner_derive::aoc_lib;
 
 mod day1;
 mod day10;
-mod day11;
-mod day12;
-mod day13;
-mod day14;
-mod day15;
+pub mod day11;
+pub mod day12;
+pub mod day13;
+pub mod day14;
+pub mod day15;
 mod day16;
 mod day17;
 mod day18;
-mod day19;
+pub mod day19;
 mod day2;
-mod day20;
-mod day21;
-mod day22;
+pub mod day20;
+pub mod day21;
+pub mod day22;
 mod day23;
 mod day24;
 mod day25;
@@ -27,6 +27,6 @@ mod day5;
 mod day6;
 mod day7;
 mod day8;
-mod day9;
+pub mod day9;
 
 aoc_lib! { year = 2021 }